@@ -0,0 +1,48 @@
+// Draws one mip level of one texture array layer by averaging 2x2 blocks of
+// the level above it
+
+// Laid out like MipParams in mipmap.rs
+struct MipParams {
+    layer: u32,
+    premultiplied: u32,
+    _padding0: u32,
+    _padding1: u32,
+}
+
+@group(0) @binding(0) var source_texture: texture_2d_array<f32>;
+@group(0) @binding(1) var<uniform> params: MipParams;
+
+// One triangle that covers the whole target
+@vertex
+fn vertex_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let position = vec2(f32(index & 1u) * 4. - 1., f32(index >> 1u) * 4. - 1.);
+    return vec4(position, 0., 1.);
+}
+
+// sRGB textures are decoded when they're loaded, and encoded when they're
+// rendered to, so the average is taken in linear space. The pixels are
+// premultiplied first, so that transparent pixels don't add their colour.
+// On odd sizes, the last row or column is left out, like on the CPU.
+@fragment
+fn pixel_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let last = vec2<i32>(textureDimensions(source_texture)) - 1;
+    let corner = vec2<i32>(position.xy) * 2;
+    var sum = vec4(0.);
+    for (var offset = 0; offset < 4; offset++) {
+        let coords = min(corner + vec2(offset & 1, offset >> 1u), last);
+        // The level is always 0, since GL ignores the view's mip levels
+        var texel = textureLoad(source_texture, coords, params.layer, 0);
+        if params.premultiplied == 0u {
+            texel = vec4(texel.rgb * texel.a, texel.a);
+        }
+        sum += texel;
+    }
+    let average = sum / 4.;
+    if params.premultiplied != 0u {
+        return average;
+    }
+    if average.a > 0. {
+        return vec4(average.rgb / average.a, average.a);
+    }
+    return vec4(0.);
+}
@@ -0,0 +1,58 @@
+// GPU version of simulation::step. Keep the two in sync, so that the CPU and
+// GPU simulations look the same.
+
+struct Flare {
+    pos: vec2<f32>,
+    vel: vec2<f32>,
+    hue: f32,
+    index: u32,
+};
+
+struct Params {
+    dt: f32,
+    hue_speed: f32,
+    count: u32,
+};
+
+const TAU: f32 = 6.28318530717958647692;
+const WORKGROUP_SIZE: u32 = 64u;
+// Size of SquareInstanceRaw, in words
+const INSTANCE_WORDS: u32 = 5u;
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> flares: array<Flare>;
+// Laid out like SquareInstanceRaw, so the square pipeline can use it as a
+// vertex buffer. That's not a size WGSL structs can have, so it's written
+// word by word. The texture layer never changes, so it isn't written.
+@group(0) @binding(2) var<storage, read_write> instances: array<u32>;
+
+@compute @workgroup_size(64)
+fn simulate(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) workgroups: vec3<u32>
+) {
+    // There can be more flares than fit in one row of workgroups
+    let i = id.x + id.y * workgroups.x * WORKGROUP_SIZE;
+    if i >= params.count {
+        return;
+    }
+    var flare = flares[i];
+    flare.pos += flare.vel * params.dt;
+    // Reflect off the edges of clip space
+    if abs(flare.pos.x) > 1.0 {
+        flare.vel.x = -flare.vel.x;
+        flare.pos.x = clamp(flare.pos.x, -1.0, 1.0);
+    }
+    if abs(flare.pos.y) > 1.0 {
+        flare.vel.y = -flare.vel.y;
+        flare.pos.y = clamp(flare.pos.y, -1.0, 1.0);
+    }
+    let hue = flare.hue + params.hue_speed * params.dt;
+    flare.hue = hue - TAU * floor(hue / TAU);
+    flares[i] = flare;
+    let base = i * INSTANCE_WORDS;
+    instances[base] = bitcast<u32>(flare.pos.x);
+    instances[base + 1u] = bitcast<u32>(flare.pos.y);
+    instances[base + 2u] = bitcast<u32>(flare.hue);
+    instances[base + 3u] = flare.index;
+}
@@ -2,19 +2,40 @@ struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) uv: vec2<f32>,
     @location(1) colour: vec3<f32>,
+    @location(2) @interpolate(flat) layer: u32,
 };
 
 const SQUARE_SIZE: f32 = 8.0; // pixels
 const PI: f32 = 3.14159265358979323846;
 
-@group(0) @binding(0) var<uniform> screen_size: vec2<u32>;
-@group(0) @binding(1) var flare_texture: texture_2d<f32>;
+// Laid out like SquareUniforms in square.rs
+struct SquareUniforms {
+    screen_size: vec2<u32>,
+    // Panning and zooming, in clip space
+    offset: vec2<f32>,
+    zoom: f32,
+    _padding: f32,
+};
+
+@group(0) @binding(0) var<uniform> uniforms: SquareUniforms;
+@group(0) @binding(1) var flare_texture: texture_2d_array<f32>;
 @group(0) @binding(2) var flare_sampler: sampler;
 
+// Where each flare texture is in the flare texture array. Has to match
+// TextureRegionRaw and MAX_TEXTURE_REGIONS.
+struct TextureRegion {
+    // Offset, then size, in UV coordinates
+    rect: vec4<f32>,
+    layer: u32,
+};
+const MAX_TEXTURE_REGIONS: u32 = 64u;
+@group(0) @binding(3) var<uniform> texture_regions: array<TextureRegion, MAX_TEXTURE_REGIONS>;
+
 @vertex
 fn vertex_main(
     @location(0) inst_pos_hue: vec4<f32>,
-    @location(1) vert_pos_uv: vec4<f32>
+    @location(1) inst_texture: u32,
+    @location(2) vert_pos_uv: vec4<f32>
 ) -> VertexOutput {
     var gazouta: VertexOutput;
     let ipos = inst_pos_hue.xy;
@@ -23,8 +44,10 @@ fn vertex_main(
     let vpos = vert_pos_uv.xy;
     let vuv = vert_pos_uv.zw;
     let depth = select(0.25, 0.125, index % 2u == 0u);
-    gazouta.position = vec4<f32>(vpos + ipos, depth, 1.0);
-    gazouta.uv = vuv;
+    gazouta.position = vec4<f32>((vpos + ipos) * uniforms.zoom + uniforms.offset, depth, 1.0);
+    let region = texture_regions[min(inst_texture, MAX_TEXTURE_REGIONS - 1u)];
+    gazouta.uv = region.rect.xy + vuv * region.rect.zw;
+    gazouta.layer = region.layer;
     gazouta.colour = 
         // From https://github.com/Talon1024/shader-shite/blob/master/hsl.frag
         clamp(cos(hue - PI * 2. * vec3<f32>(0., 0.333333333333, 0.666666666666)) + .5, vec3(0.0), vec3(1.0));
@@ -33,10 +56,22 @@ fn vertex_main(
 
 @fragment
 fn pixel_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
-    let tex_colour = textureSample(flare_texture, flare_sampler, vertex.uv);
+    let tex_colour = textureSample(flare_texture, flare_sampler, vertex.uv, vertex.layer);
     // Assuming green and blue channels are the same, I can change the
     // hue easily
     let blend_factor = tex_colour.r - tex_colour.g;
     let colour = mix(vec3(1.0), vertex.colour, blend_factor);
     return vec4(colour, tex_colour.a);
 }
+
+// For blend modes that use a premultiplied texture. This gives the same
+// colour as pixel_main, premultiplied by alpha.
+@fragment
+fn pixel_main_premultiplied(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let tex_colour = textureSample(flare_texture, flare_sampler, vertex.uv, vertex.layer);
+    // (r - g) is already multiplied by alpha, so
+    // alpha * mix(1, colour, r - g) = alpha + (colour - 1) * (r - g)
+    let blend_factor = tex_colour.r - tex_colour.g;
+    let colour = vec3(tex_colour.a) + (vertex.colour - vec3(1.0)) * blend_factor;
+    return vec4(colour, tex_colour.a);
+}
@@ -1,19 +1,28 @@
 use std::error::Error;
 
-use glam::Vec2;
-use wgpu::{*, util::{DeviceExt, BufferInitDescriptor}};
+use wgpu::*;
 use winit::{
-    dpi::{LogicalSize, PhysicalSize},
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    event::{ElementState, MouseScrollDelta},
     event_loop::{EventLoop, EventLoopBuilder, EventLoopProxy},
     window::{Window, WindowBuilder},
 };
 
-#[cfg(target_family = "wasm")]
-use wasm_bindgen::JsValue;
-
 use crate::{
-    square::{SquarePipeline, SquareUniforms, SquareInstance, SquareInstanceRaw},
-    util::{surface::SurfaceInfo, texture::SimpleTextureView},
+    command::Command,
+    config::BenchConfig,
+    frame_limiter::FrameLimiter,
+    platform,
+    gpu_timer::GpuTimer,
+    report::RunInfo,
+    scene::Scene,
+    stats::FrameStats,
+    sweep::Sweep,
+    util::{
+        context::{create_instance, GpuContext, GpuContextOptions},
+        screenshot::Screenshot,
+        surface::SurfaceInfo,
+    },
 };
 
 pub struct CreatedWindow<T: 'static> {
@@ -21,134 +30,480 @@ pub struct CreatedWindow<T: 'static> {
     pub event_loop: EventLoop<T>,
 }
 
-pub fn create_window() -> Result<CreatedWindow<AppEvent>, Box<dyn Error>> {
+pub fn create_window(width: u32, height: u32) -> Result<CreatedWindow<AppEvent>, Box<dyn Error>> {
     let event_loop = EventLoopBuilder::with_user_event().build();
     let window = WindowBuilder::new()
-        .with_inner_size(LogicalSize::new(640, 480))
+        .with_inner_size(LogicalSize::new(width, height))
         .build(&event_loop)?;
     Ok(CreatedWindow { window, event_loop })
 }
 
-pub enum AppEvent {}
+/// Things that happen outside of the event loop's own events, mostly the
+/// results of work done in the background. They're sent through an
+/// `EventLoopProxy`.
+pub enum AppEvent {
+    /// The app has been set up, and its assets have been loaded. Sent once,
+    /// by the task that `run` starts.
+    Loaded(Result<Box<AppState>, String>),
+    /// Everything on the GPU has been made again, after the device was lost
+    /// or a setting that the pipelines depend on was changed. The app is sent
+    /// back even if that didn't work, so that the results so far can be
+    /// written.
+    Rebuilt(Box<AppState>, Result<(), String>),
+    /// The run has moved on to another phase
+    PhaseChanged(Phase),
+    /// A screenshot has been read back from the GPU, and can be saved
+    ScreenshotReady(Result<(), String>),
+    /// GPU render pass times have been read back, and can be collected
+    GpuTimesReady,
+    Command(Command),
+}
+
+/// The stages a benchmark run goes through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Frames are rendered, but left out of the results
+    Warmup,
+    /// Frames are being recorded
+    Measuring,
+    /// Everything that was asked for has been rendered
+    Finished,
+}
 
 pub struct AppState {
-    instance: Instance,
     pub window: Window,
-    device: Device,
-    queue: Queue,
+    context: GpuContext,
     surface_info: SurfaceInfo,
     event_loop_proxy: EventLoopProxy<AppEvent>,
-    square_pipeline: SquarePipeline,
-    square_uniforms: SquareUniforms,
-    square_instances: Vec<SquareInstance>,
-    square_instance_count: u32,
-    square_instance_buffer: Buffer,
+    scene: Scene,
+    pub stats: FrameStats,
+    gpu_timer: Option<GpuTimer>,
+    config: BenchConfig,
+    start_time: f64,
+    pub sweep: Option<Sweep>,
+    /// Whether the window is hidden, so that nothing would be shown
+    occluded: bool,
+    frame_limiter: FrameLimiter,
+    phase: Phase,
+    /// Whether to copy the next frame for a screenshot
+    screenshot_requested: bool,
+    /// A screenshot that's being read back
+    screenshot: Option<Screenshot>,
+    /// How many frames have been rendered over the whole run. Unlike the
+    /// frame stats, this never starts over.
+    frames_rendered: u32,
+    /// Whether the scene has to be made again, because the blend mode or
+    /// sample count has changed
+    pending_reload: bool,
+    /// Where the mouse cursor is in the window, if it's in the window
+    cursor: Option<PhysicalPosition<f64>>,
+    /// Whether the view is being dragged around with the mouse
+    dragging: bool,
 }
 
+/// How much one line of mouse wheel scrolling zooms in or out
+const ZOOM_PER_LINE: f32 = 1.1;
+/// How many pixels of touchpad scrolling count as one line
+const PIXELS_PER_LINE: f64 = 40.;
+
 impl AppState {
     pub async fn setup(
         window: Window,
         primary_proxy: EventLoopProxy<AppEvent>,
+        config: BenchConfig,
     ) -> Result<AppState, Box<dyn Error>> {
-        let instance = Instance::new(InstanceDescriptor {
-            backends: Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
-        let (surface_info, device, queue) = SurfaceInfo::create(&instance, &window).await?;
-        let backend = format!("wgpu backend: {:?}", surface_info.backend);
-        #[cfg(not(target_family = "wasm"))]
-        println!("{backend}");
-        #[cfg(target_family = "wasm")]
-        web_sys::console::log_1(&JsValue::from_str(&backend));
-
+        let options = GpuContextOptions::from_config(&config, false);
+        let instance = create_instance(options.backends);
+        // In order for the adapter to be able to render to the surface, the
+        // adapter needs a surface to be compatible with.
+        let surface = unsafe { instance.create_surface(&window) }?;
+        let context = GpuContext::new(instance, &options, Some(&surface)).await?;
         let screen_size = window.inner_size();
-        let flare_texture = crate::util::texture::Texture::load_asset(
-            &device, &queue, "assets/redflare2.png", None).await?;
-        let square_pipeline = SquarePipeline::new(&device, &flare_texture, surface_info.format()).await?;
-        let square_uniforms = SquareUniforms { screen_size: [screen_size.width, screen_size.height] };
-        queue.write_buffer(&square_pipeline.uniform_buffer, 0, bytemuck::cast_slice(&[square_uniforms]));
-        let square_instances = vec![SquareInstance {
-            pos: Vec2::new(0.0625, 0.0625),
-            hue: std::f32::consts::PI,
-            index: 0,
-        }, SquareInstance {
-            pos: Vec2::new(-0.0625, -0.0625),
-            hue: 0.0,
-            index: 1,
-        }];
-        let square_instance_count = square_instances.len() as u32;
-        let square_instance_data: Vec<_> = square_instances.iter().copied().map(SquareInstanceRaw::from).collect();
-        let square_instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Square instance buffer"),
-            contents: bytemuck::cast_slice(&square_instance_data),
-            usage: BufferUsages::VERTEX,
-        });
+        let surface_info = SurfaceInfo::new(
+            &context, surface, screen_size, config.present_mode, config.srgb, config.sample_count)?;
+        // I like to show the user which backend is being used once they start
+        // the app.
+        platform::log(&format!("wgpu backend: {:?}", context.adapter_info.backend));
+
+        let gpu_timer = GpuTimer::new(&context.device, &context.queue);
+        if gpu_timer.is_none() {
+            platform::log("Timestamp queries are not supported, so GPU times won't be measured");
+        }
+        let mut scene = Scene::new(&context, surface_info.format(),
+            surface_info.sample_count(), (screen_size.width, screen_size.height), &config).await?;
+        let sweep = config.sweep.map(|sweep| Sweep::new(sweep, scene.max_instance_count(&context.device)));
+        if let Some(sweep) = &sweep {
+            scene.set_instance_count(&context.device, sweep.instance_count());
+        }
         Ok(AppState {
-            instance,
             window,
-            device,
-            queue,
+            context,
             surface_info,
-            square_pipeline,
-            square_instances,
-            square_instance_count,
-            square_instance_buffer,
-            square_uniforms,
+            scene,
+            stats: FrameStats::new(config.warmup_frames),
+            gpu_timer,
+            frame_limiter: FrameLimiter::new(config.max_frame_rate),
+            config,
+            start_time: platform::now(),
+            sweep,
+            occluded: false,
+            phase: Phase::Warmup,
+            screenshot_requested: false,
+            screenshot: None,
+            frames_rendered: 0,
+            pending_reload: false,
+            cursor: None,
+            dragging: false,
             event_loop_proxy: primary_proxy,
         })
     }
+    /// A proxy for sending events to the event loop from background tasks
+    pub fn proxy(&self) -> EventLoopProxy<AppEvent> {
+        self.event_loop_proxy.clone()
+    }
+    fn send(&self, event: AppEvent) {
+        // This only fails if the event loop has stopped, and then there's
+        // nobody to tell
+        let _ = self.event_loop_proxy.send_event(event);
+    }
+    /// Start the frame stats over. GPU times that are still being read back
+    /// are for frames from before, so they're thrown away. The surface events
+    /// are kept, since they're counted over the whole run.
+    fn reset_stats(&mut self) {
+        let surface_events = self.stats.surface_events();
+        self.stats = FrameStats::new(self.config.warmup_frames);
+        *self.stats.surface_events_mut() = surface_events;
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            timer.discard_pending();
+        }
+    }
+    /// Switch to the next present mode the surface supports. The frame stats
+    /// start over, so that they only cover one present mode.
+    pub fn next_present_mode(&mut self) {
+        let present_mode = self.surface_info.next_present_mode(&self.context.device);
+        self.reset_stats();
+        platform::log(&format!("Present mode: {present_mode:?}"));
+    }
+    /// Switch each layer to the blend mode after the one it's using, so that
+    /// there are as many layers as before. The pipelines are made again by
+    /// `rebuild`.
+    fn next_blend_mode(&mut self) {
+        let blend_modes: Vec<_> = self.scene.blend_modes().iter().map(|mode| mode.next()).collect();
+        let names: Vec<String> = blend_modes.iter().map(ToString::to_string).collect();
+        platform::log(&format!("Blend mode: {}", names.join("+")));
+        self.config.blend_modes = blend_modes;
+        self.pending_reload = true;
+    }
+    /// Switch to the next supported sample count. The pipelines are made
+    /// again by `rebuild`.
+    fn next_sample_count(&mut self) {
+        let sample_count = self.surface_info.next_sample_count(&self.context);
+        self.config.sample_count = sample_count;
+        self.pending_reload = true;
+        platform::log(&format!("MSAA: {sample_count}x"));
+    }
+    /// Draw `count` flares, or as many as the device can, if that's fewer
+    fn set_instance_count(&mut self, count: u32) {
+        let max = self.scene.max_instance_count(&self.context.device);
+        if count > max {
+            platform::log(&format!("The device can't draw more than {max} instances"));
+        }
+        let count = count.min(max);
+        self.scene.set_instance_count(&self.context.device, count);
+        // Like with the present mode, the stats only cover one instance count
+        self.reset_stats();
+        platform::log(&format!("Instances: {count}"));
+    }
+    /// Do what a command says. Stopping the run is up to the event loop, so
+    /// `Command::Quit` does nothing here. Changing the blend mode or sample
+    /// count leaves the app needing a `rebuild`.
+    pub fn run_command(&mut self, command: Command) {
+        match command {
+            Command::NextPresentMode => self.next_present_mode(),
+            Command::NextBlendMode => self.next_blend_mode(),
+            Command::NextSampleCount => self.next_sample_count(),
+            Command::SetInstanceCount(count) => self.set_instance_count(count),
+            Command::MoreInstances => self.set_instance_count(self.scene.instance_count().saturating_mul(2)),
+            Command::FewerInstances => self.set_instance_count((self.scene.instance_count() / 2).max(1)),
+            Command::TogglePause => {
+                let paused = !self.scene.paused();
+                self.scene.set_paused(paused);
+                platform::log(if paused { "Paused" } else { "Unpaused" });
+            }
+            Command::Step => self.scene.step_once(),
+            Command::ResetLayout => self.scene.reset_layout(&self.context.device),
+            Command::ResetView => self.scene.reset_view(&self.context.queue),
+            Command::Screenshot if !self.surface_info.can_copy() => {
+                platform::log("This surface can't be copied from, so screenshots can't be taken");
+            }
+            Command::Screenshot => self.screenshot_requested = true,
+            Command::Quit => (),
+        }
+    }
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+    /// Work out which phase the run is in, and send `AppEvent::PhaseChanged`
+    /// if it's moved on
+    pub fn update_phase(&mut self) {
+        let phase = if self.finished() {
+            Phase::Finished
+        } else if self.stats.frame_count() > self.stats.warmup_frames() {
+            Phase::Measuring
+        } else {
+            Phase::Warmup
+        };
+        if phase != self.phase {
+            self.phase = phase;
+            self.send(AppEvent::PhaseChanged(phase));
+        }
+    }
+    /// Save the screenshot that's been read back, as a PNG file named after
+    /// the frame it was taken on
+    pub fn save_screenshot(&mut self, mapped: Result<(), String>) {
+        let Some(screenshot) = self.screenshot.take() else {
+            return;
+        };
+        let filename = format!("screenshot_{}.png", screenshot.frame());
+        let saved = mapped.map_err(Box::from)
+            .and_then(|()| screenshot.to_png())
+            .and_then(|png| platform::write_binary_output(&filename, &png));
+        match saved {
+            Ok(()) => platform::log(&format!("Saved {filename}")),
+            Err(error) => platform::log(&format!("Could not save the screenshot: {error}")),
+        }
+    }
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         // recreate the window surface
-        self.surface_info.resize(&self.device, new_size);
-        self.square_uniforms = SquareUniforms { screen_size: [new_size.width, new_size.height] };
-        self.queue.write_buffer(&self.square_pipeline.uniform_buffer, 0, bytemuck::cast_slice(&[self.square_uniforms]))
+        self.surface_info.resize(&self.context.device, new_size);
+        if !self.surface_info.is_zero_sized() {
+            self.scene.resize(&self.context.queue, (new_size.width, new_size.height));
+        }
+    }
+    /// Whether the device has been lost, so that `rebuild` has to be called
+    /// before anything else is rendered
+    pub fn device_lost(&self) -> bool {
+        self.context.device_lost()
+    }
+    /// For when wgpu panics part way through a frame because the device was
+    /// lost. What was left of the frame is thrown away: it isn't counted as
+    /// a frame, and a screenshot being read back from it won't arrive.
+    pub fn set_device_lost(&mut self) {
+        self.context.set_device_lost();
+        self.screenshot = None;
+        self.skip_frame();
     }
-    pub fn render(&self) -> Result<(), Box<dyn Error>> {
+    /// Whether `rebuild` has to be called before anything else is rendered
+    pub fn needs_rebuild(&self) -> bool {
+        self.device_lost() || self.pending_reload
+    }
+    /// Make the scene again, with the same instance count, view, and pausing.
+    /// If the device was lost, a new one is opened first, and everything else
+    /// is made again on it too: the surface configuration, depth and
+    /// multisampled textures, and timer. The stats and the sweep carry on
+    /// after a lost device, but start over after a change of settings.
+    pub async fn rebuild(&mut self) -> Result<(), Box<dyn Error>> {
+        let device_lost = self.device_lost();
+        if device_lost {
+            let options = GpuContextOptions::from_config(&self.config, false);
+            self.context.recreate(&options, Some(&self.surface_info.surface)).await?;
+            self.surface_info.recreate(&self.context);
+            self.gpu_timer = GpuTimer::new(&self.context.device, &self.context.queue);
+            // The screenshot was being read back from the old device
+            self.screenshot = None;
+        }
+        let size = self.window.inner_size();
+        let mut scene = Scene::new(&self.context, self.surface_info.format(),
+            self.surface_info.sample_count(), (size.width.max(1), size.height.max(1)), &self.config).await?;
+        scene.carry_over(&self.context.device, &self.context.queue, &self.scene);
+        self.scene = scene;
+        self.pending_reload = false;
+        if device_lost {
+            self.stats.surface_events_mut().device_lost += 1;
+            // The time spent rebuilding isn't a frame time
+            self.skip_frame();
+            platform::log(&format!("Rebuilt everything on {}", self.context.adapter_info.name));
+        } else {
+            self.reset_stats();
+        }
+        Ok(())
+    }
+    pub fn mouse_button(&mut self, state: ElementState) {
+        self.dragging = state == ElementState::Pressed;
+    }
+    /// Convert a position in the window to clip space
+    fn to_clip_space(&self, position: PhysicalPosition<f64>) -> [f32; 2] {
+        let size = self.window.inner_size();
+        let (width, height) = (size.width.max(1) as f64, size.height.max(1) as f64);
+        [(position.x / width * 2. - 1.) as f32, (1. - position.y / height * 2.) as f32]
+    }
+    /// Follow the cursor, and pan the view if it's being dragged
+    pub fn cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        if let Some(last) = self.cursor.filter(|_| self.dragging) {
+            let (from, to) = (self.to_clip_space(last), self.to_clip_space(position));
+            self.scene.pan(&self.context.queue, [to[0] - from[0], to[1] - from[1]]);
+        }
+        self.cursor = Some(position);
+    }
+    pub fn cursor_left(&mut self) {
+        self.cursor = None;
+        self.dragging = false;
+    }
+    /// Zoom the view around the cursor, or the middle of the window if the
+    /// cursor isn't in it
+    pub fn mouse_wheel(&mut self, delta: MouseScrollDelta) {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, lines) => lines,
+            MouseScrollDelta::PixelDelta(pixels) => (pixels.y / PIXELS_PER_LINE) as f32,
+        };
+        let around = self.cursor.map_or([0., 0.], |cursor| self.to_clip_space(cursor));
+        self.scene.zoom(&self.context.queue, ZOOM_PER_LINE.powf(lines), around);
+    }
+    pub fn set_occluded(&mut self, occluded: bool) {
+        self.occluded = occluded;
+    }
+    /// How many milliseconds to wait before the next frame, to keep under
+    /// the frame rate cap
+    pub fn time_until_next_frame(&self) -> f64 {
+        self.frame_limiter.wait(platform::now())
+    }
+    /// Whether there's anywhere to render to. Frames are skipped while the
+    /// window is minimized or hidden.
+    pub fn can_render(&self) -> bool {
+        !self.occluded && !self.surface_info.is_zero_sized()
+    }
+    /// Call this instead of rendering a frame
+    fn skip_frame(&mut self) {
+        self.stats.skip_frame();
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.skip_frame();
+        }
+    }
+    /// Get the next surface texture. Errors that can be recovered from are
+    /// counted and dealt with here, and the frame is skipped by returning
+    /// `Ok(None)`. Running out of memory can't be recovered from.
+    fn next_surface_texture(&mut self) -> Result<Option<SurfaceTexture>, SurfaceError> {
+        if !self.can_render() {
+            let events = self.stats.surface_events_mut();
+            if self.occluded {
+                events.occluded_frames += 1;
+            } else {
+                events.zero_size_frames += 1;
+            }
+            self.skip_frame();
+            return Ok(None);
+        }
+        let error = match self.surface_info.get_current_texture() {
+            Ok(texture) => return Ok(Some(texture)),
+            Err(error) => error,
+        };
+        let events = self.stats.surface_events_mut();
+        match error {
+            SurfaceError::Lost => events.lost += 1,
+            SurfaceError::Outdated => events.outdated += 1,
+            SurfaceError::Timeout => events.timeouts += 1,
+            SurfaceError::OutOfMemory => {
+                events.out_of_memory += 1;
+                return Err(error);
+            }
+        }
+        platform::log(&format!("Skipping a frame: {error}"));
+        if matches!(error, SurfaceError::Lost | SurfaceError::Outdated) {
+            self.surface_info.reconfigure(&self.context.device);
+        }
+        self.skip_frame();
+        Ok(None)
+    }
+    /// Whether the configured number of frames have been rendered, or the
+    /// configured amount of time has passed. Both are counted from the start
+    /// of the run, even if the frame stats have started over since.
+    fn finished(&self) -> bool {
+        self.sweep.as_ref().is_some_and(Sweep::done) ||
+        self.config.finished(self.frames_rendered, platform::now() - self.start_time)
+    }
+    pub fn run_info(&self) -> RunInfo {
+        let size = self.window.inner_size();
+        RunInfo::new(
+            &self.context.adapter_info,
+            self.surface_info.format(),
+            self.surface_info.sample_count(),
+            Some(self.surface_info.present_mode()),
+            &self.scene,
+            (size.width, size.height),
+        )
+    }
+    /// Render a frame, or skip it if the surface isn't available. The only
+    /// error is running out of memory, after which the run should stop.
+    pub fn render(&mut self) -> Result<(), Box<dyn Error>> {
         // Get the output texture to render to
-        let canvas = self.surface_info.get_current_texture()?;
-        let canvas_view = SimpleTextureView::new(&canvas.texture, Some("Surface view"));
+        let Some(canvas) = self.next_surface_texture()? else {
+            return Ok(());
+        };
+        let now = platform::now();
+        self.frame_limiter.begin_frame(now);
+        if let Some(count) = self.sweep.as_mut().and_then(|sweep| sweep.begin_frame(now)) {
+            self.scene.set_instance_count(&self.context.device, count);
+        }
+        if self.sweep.as_ref().is_some_and(Sweep::done) {
+            return Ok(());
+        }
+        self.stats.begin_frame(now);
+        self.scene.update(now);
+        let canvas_view = self.surface_info.view(&canvas);
         let mut commands = self
+            .context
             .device
             .create_command_encoder(&CommandEncoderDescriptor {
                 label: Some("My commands"),
             });
-        {
-            let mut render_pass = commands.begin_render_pass(&RenderPassDescriptor {
-                label: Some("My render pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &canvas_view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color {
-                            r: 0.125,
-                            g: 0.125,
-                            b: 0.25,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                    view: &self.surface_info.depth_texture_view,
-                    depth_ops: Some(Operations {
-                        load: LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
-            render_pass.set_pipeline(&self.square_pipeline);
-            render_pass.set_vertex_buffer(0, self.square_instance_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.square_pipeline.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.square_pipeline.index_buffer.slice(..), IndexFormat::Uint16);
-            render_pass.set_bind_group(0, &self.square_pipeline.bind_group, &[]);
-            render_pass.draw_indexed(
-                0..crate::square::SQUARE_INDX.len() as u32,
-                0,
-                0..self.square_instance_count);
-        }
-        self.queue.submit([commands.finish()]);
+        // Upload outside of the timed section, so that the copies don't count
+        // towards the render pass time
+        if let Some(time) = self.scene.upload(&self.context.device, &self.context.queue, &mut commands) {
+            self.stats.record_upload_time(self.stats.frame(), time);
+        }
+        self.scene.simulate(&self.context.device, &self.context.queue, &mut commands);
+        let target = self.surface_info.render_target(&canvas_view);
+        match self.gpu_timer.as_mut() {
+            Some(timer) => timer.time(&mut commands, self.stats.frame(), |commands| {
+                self.scene.encode(commands, &target);
+            }),
+            None => self.scene.encode(&mut commands, &target),
+        }
+        let take_screenshot = self.screenshot.is_none() && std::mem::take(&mut self.screenshot_requested);
+        if take_screenshot {
+            match Screenshot::copy(&self.context.device, &mut commands, &canvas.texture, self.frames_rendered) {
+                Ok(screenshot) => self.screenshot = Some(screenshot),
+                Err(error) => platform::log(&format!("Could not take a screenshot: {error}")),
+            }
+        }
+        self.context.queue.submit([commands.finish()]);
         canvas.present();
+        if let Some(screenshot) = self.screenshot.as_ref().filter(|_| take_screenshot) {
+            let proxy = self.proxy();
+            screenshot.map(move |result| {
+                let _ = proxy.send_event(AppEvent::ScreenshotReady(result.map_err(|error| error.to_string())));
+            });
+        }
+        if self.screenshot.is_some() {
+            // The screenshot is only mapped when the device is polled
+            self.context.device.poll(Maintain::Poll);
+        }
+        self.scene.after_submit();
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            let proxy = self.event_loop_proxy.clone();
+            timer.after_submit(move || {
+                let _ = proxy.send_event(AppEvent::GpuTimesReady);
+            });
+        }
+        self.frames_rendered += 1;
         Ok(())
     }
+    /// Record the GPU times that have been read back
+    pub fn collect_gpu_times(&mut self) {
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            for (frame, time) in timer.collect() {
+                self.stats.record_gpu_time(frame, time);
+            }
+        }
+    }
 }
@@ -0,0 +1,136 @@
+use std::{fmt, str::FromStr};
+
+use winit::event_loop::EventLoopProxy;
+
+use crate::app::AppEvent;
+
+/// Something to do to a running benchmark, from the keyboard or from outside
+/// the app
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Switch to the next present mode the surface supports
+    NextPresentMode,
+    /// Switch to the next blend mode
+    NextBlendMode,
+    /// Switch to the next sample count the device supports, going back to no
+    /// MSAA after the largest
+    NextSampleCount,
+    /// Draw this many flares, up to the most the device can draw
+    SetInstanceCount(u32),
+    /// Draw twice as many flares, up to the most the device can draw
+    MoreInstances,
+    /// Draw half as many flares
+    FewerInstances,
+    /// Stop or start moving the flares
+    TogglePause,
+    /// Move the flares one step, while they're paused
+    Step,
+    /// Put the flares back where they started
+    ResetLayout,
+    /// Undo any panning and zooming
+    ResetView,
+    /// Save the next frame to a PNG file
+    Screenshot,
+    /// Write the results and stop
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    /// Parse a command like "present-mode", "instances 1000", "pause",
+    /// "screenshot", or "quit". The other commands are "blend", "msaa",
+    /// "more-instances", "fewer-instances", "step", "reset-layout", and
+    /// "reset-view".
+    fn from_str(command: &str) -> Result<Self, Self::Err> {
+        let mut words = command.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("present-mode"), None) => Command::NextPresentMode,
+            (Some("blend"), None) => Command::NextBlendMode,
+            (Some("msaa"), None) => Command::NextSampleCount,
+            (Some("instances"), Some(count)) => Command::SetInstanceCount(
+                count.parse().map_err(|_| format!("{count:?} is not an instance count"))?),
+            (Some("more-instances"), None) => Command::MoreInstances,
+            (Some("fewer-instances"), None) => Command::FewerInstances,
+            (Some("pause"), None) => Command::TogglePause,
+            (Some("step"), None) => Command::Step,
+            (Some("reset-layout"), None) => Command::ResetLayout,
+            (Some("reset-view"), None) => Command::ResetView,
+            (Some("screenshot"), None) => Command::Screenshot,
+            (Some("quit"), None) => Command::Quit,
+            _ => return Err(format!("Unknown command {command:?}")),
+        };
+        match words.next() {
+            Some(_) => Err(String::from("Too many arguments")),
+            None => Ok(command),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::NextPresentMode => f.write_str("present-mode"),
+            Command::NextBlendMode => f.write_str("blend"),
+            Command::NextSampleCount => f.write_str("msaa"),
+            Command::SetInstanceCount(count) => write!(f, "instances {count}"),
+            Command::MoreInstances => f.write_str("more-instances"),
+            Command::FewerInstances => f.write_str("fewer-instances"),
+            Command::TogglePause => f.write_str("pause"),
+            Command::Step => f.write_str("step"),
+            Command::ResetLayout => f.write_str("reset-layout"),
+            Command::ResetView => f.write_str("reset-view"),
+            Command::Screenshot => f.write_str("screenshot"),
+            Command::Quit => f.write_str("quit"),
+        }
+    }
+}
+
+/// Read commands from stdin, one per line, and pass them on to the event
+/// loop. This is how scripts can control a long run.
+#[cfg(not(target_family = "wasm"))]
+pub fn read_stdin(proxy: EventLoopProxy<AppEvent>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse() {
+                Ok(command) => {
+                    if proxy.send_event(AppEvent::Command(command)).is_err() {
+                        // The event loop has stopped
+                        return;
+                    }
+                }
+                Err(error) => crate::platform::log(&error),
+            }
+        }
+    });
+}
+
+#[cfg(target_family = "wasm")]
+thread_local! {
+    static PROXY: std::cell::RefCell<Option<EventLoopProxy<AppEvent>>> = Default::default();
+}
+
+/// Let `send_command` pass commands on to the event loop
+#[cfg(target_family = "wasm")]
+pub fn set_proxy(proxy: EventLoopProxy<AppEvent>) {
+    PROXY.with(|stored| *stored.borrow_mut() = Some(proxy));
+}
+
+/// Send a command to the running benchmark from JavaScript. See
+/// `Command::from_str` for what commands there are.
+#[cfg(target_family = "wasm")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+pub fn send_command(command: &str) -> Result<(), String> {
+    let command = command.parse()?;
+    PROXY.with(|proxy| match proxy.borrow().as_ref() {
+        Some(proxy) => proxy.send_event(AppEvent::Command(command))
+            .map_err(|_| String::from("The benchmark has stopped")),
+        None => Err(String::from("The benchmark hasn't started")),
+    })
+}
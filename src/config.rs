@@ -0,0 +1,111 @@
+use wgpu::{Backends, PowerPreference, PresentMode};
+
+use crate::{
+    flare_textures::TexturePacking,
+    layout::{Layout, DEFAULT_SEED},
+    square::BlendMode,
+    sweep::SweepConfig,
+    upload::UploadStrategy,
+    util::mipmap::MipGeneration,
+};
+
+pub const DEFAULT_TEXTURES: [&str; 3] = ["assets/redflare2.png", "assets/redflare.png", "assets/flare.png"];
+
+/// Everything about a benchmark run that can be changed without recompiling
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub width: u32,
+    pub height: u32,
+    pub backends: Backends,
+    pub power_preference: PowerPreference,
+    /// Whether to try the fallback (software) adapter before any hardware
+    /// adapters. If this is `None`, headless runs prefer the fallback adapter,
+    /// and windowed runs don't.
+    pub prefer_fallback_adapter: Option<bool>,
+    /// Falls back to a supported present mode if the surface doesn't support
+    /// this one. The default doesn't wait for vsync, so that frame times
+    /// aren't capped at the refresh rate.
+    pub present_mode: PresentMode,
+    /// Gamma-encode the output. If this is off, an sRGB surface is viewed as
+    /// linear, so the two can be compared.
+    pub srgb: bool,
+    /// MSAA sample count: 1, 2, 4, or 8. Lowered if the adapter doesn't
+    /// support it.
+    pub sample_count: u32,
+    /// Use alpha-to-coverage when multisampling
+    pub alpha_to_coverage: bool,
+    /// How the flares are blended. With more than one blend mode, the flares
+    /// are split into layers, one for each blend mode.
+    pub blend_modes: Vec<BlendMode>,
+    pub instance_count: u32,
+    pub layout: Layout,
+    /// Seed for the random parts of the layout
+    pub seed: u64,
+    /// Move the flares around every frame. If this is off, the instance
+    /// buffer is only uploaded once.
+    pub animate: bool,
+    /// How many times a second the flares are moved. They're moved in steps
+    /// of the same size, however fast the frames are rendered.
+    pub update_rate: f64,
+    /// How the instances are uploaded when they move
+    pub upload: UploadStrategy,
+    /// Move the flares with a compute shader, if the device can run one
+    pub gpu_simulation: bool,
+    /// Flare textures, which are loaded into the layers of one texture array
+    pub textures: Vec<String>,
+    /// Whether the flare textures go in a texture array or an atlas
+    pub texture_packing: TexturePacking,
+    /// Whether the flare textures' mips are made on the CPU or the GPU
+    pub mip_generation: MipGeneration,
+    pub warmup_frames: u32,
+    /// Don't start frames more often than this many times a second
+    pub max_frame_rate: Option<f64>,
+    /// Stop after this many frames
+    pub frames: Option<u32>,
+    /// Stop after this many seconds
+    pub duration: Option<f64>,
+    /// Search for the largest instance count that fits in a frame time
+    /// budget, instead of using `instance_count`
+    pub sweep: Option<SweepConfig>,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 480,
+            backends: Backends::all(),
+            power_preference: PowerPreference::HighPerformance,
+            prefer_fallback_adapter: None,
+            present_mode: PresentMode::AutoNoVsync,
+            srgb: true,
+            sample_count: 1,
+            alpha_to_coverage: true,
+            blend_modes: vec![BlendMode::default()],
+            instance_count: 2,
+            layout: Layout::default(),
+            seed: DEFAULT_SEED,
+            animate: true,
+            update_rate: crate::simulation::DEFAULT_UPDATE_RATE,
+            upload: UploadStrategy::default(),
+            gpu_simulation: true,
+            textures: DEFAULT_TEXTURES.map(String::from).to_vec(),
+            texture_packing: TexturePacking::default(),
+            mip_generation: MipGeneration::default(),
+            warmup_frames: crate::stats::DEFAULT_WARMUP_FRAMES,
+            max_frame_rate: None,
+            frames: None,
+            duration: None,
+            sweep: None,
+        }
+    }
+}
+
+impl BenchConfig {
+    /// Whether the run should stop, given how many frames have been rendered
+    /// and how many milliseconds have passed since it started.
+    pub fn finished(&self, frames: u32, elapsed: f64) -> bool {
+        self.frames.is_some_and(|limit| frames >= limit) ||
+        self.duration.is_some_and(|limit| elapsed >= limit * 1000.)
+    }
+}
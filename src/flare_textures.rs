@@ -0,0 +1,122 @@
+use std::{error::Error, fmt};
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    platform,
+    util::{atlas::Atlas, compressed::LoadedImage, context::GpuContext, mipmap::{MipGeneration, MipGenerator}, texture::Texture},
+};
+
+/// How many flare textures there can be. The table of texture regions is a
+/// uniform buffer, since WebGL2 can't read storage buffers in the vertex
+/// shader, so it has a fixed size. Has to match square.wgsl.
+pub const MAX_TEXTURE_REGIONS: usize = 64;
+
+/// How several flare textures are put into one texture
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TexturePacking {
+    /// One layer of a texture array each
+    #[default]
+    Array,
+    /// Side by side in one big texture
+    Atlas,
+}
+
+impl fmt::Display for TexturePacking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TexturePacking::Array => "array",
+            TexturePacking::Atlas => "atlas",
+        })
+    }
+}
+
+/// Where a flare texture is: a rectangle in UV coordinates, on a layer of the
+/// texture array. Laid out like `TextureRegion` in square.wgsl.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct TextureRegionRaw {
+    /// Offset, then size
+    rect: [f32; 4],
+    layer: u32,
+    _padding: [u32; 3],
+}
+
+/// All the flare textures, packed one way or the other. Instances pick a
+/// flare texture by its index in the region table, so the shader doesn't
+/// care how the textures were packed.
+pub struct FlareTextures {
+    pub texture: Texture,
+    /// The same textures, premultiplied, if a blend mode needs them
+    pub premultiplied: Option<Texture>,
+    regions: Vec<TextureRegionRaw>,
+}
+
+impl FlareTextures {
+    pub async fn load(
+        context: &GpuContext,
+        paths: &[String],
+        packing: TexturePacking,
+        premultiplied: bool,
+        mip_generation: MipGeneration,
+    ) -> Result<Self, Box<dyn Error>> {
+        if paths.len() > MAX_TEXTURE_REGIONS {
+            return Err(Box::from(format!("There can't be more than {MAX_TEXTURE_REGIONS} flare textures")));
+        }
+        let start = platform::now();
+        let mip_generator = match mip_generation {
+            MipGeneration::Cpu => None,
+            MipGeneration::Gpu => Some(MipGenerator::new(&context.device).await?),
+        };
+        let mip_generator = mip_generator.as_ref();
+        // Both the plain and the premultiplied textures are made from the
+        // same images, so they're only read and decoded once
+        let mut images = LoadedImage::load_assets(paths).await?;
+        let (texture, premultiplied, regions) = match packing {
+            TexturePacking::Array => {
+                let mut load = |label, premultiply| Texture::from_loaded(
+                    context, &mut images, Some(label), premultiply, mip_generator);
+                let texture = load("Flare texture", false)?;
+                let premultiplied = match premultiplied {
+                    true => Some(load("Premultiplied flare texture", true)?),
+                    false => None,
+                };
+                let regions = (0..texture.layer_count()).map(|layer| TextureRegionRaw {
+                    rect: [0., 0., 1., 1.],
+                    layer,
+                    _padding: [0; 3],
+                }).collect();
+                (texture, premultiplied, regions)
+            }
+            TexturePacking::Atlas => {
+                let mut load = |label, premultiply| Atlas::from_loaded(
+                    context, &mut images, Some(label), premultiply, mip_generator);
+                let atlas = load("Flare atlas", false)?;
+                let premultiplied = match premultiplied {
+                    true => Some(load("Premultiplied flare atlas", true)?.texture),
+                    false => None,
+                };
+                let regions = atlas.regions.iter().map(|region| TextureRegionRaw {
+                    rect: [region.offset[0], region.offset[1], region.size[0], region.size[1]],
+                    layer: 0,
+                    _padding: [0; 3],
+                }).collect();
+                (atlas.texture, premultiplied, regions)
+            }
+        };
+        platform::log(&format!(
+            "Loaded the flare textures in {:.1} ms, with mips made on the {mip_generation}",
+            platform::now() - start));
+        Ok(Self { texture, premultiplied, regions })
+    }
+    /// How many flare textures there are
+    pub fn region_count(&self) -> u32 {
+        self.regions.len() as u32
+    }
+    /// The region table, padded out to the size of the uniform buffer
+    pub fn region_data(&self) -> Vec<TextureRegionRaw> {
+        let mut regions = self.regions.clone();
+        regions.resize(MAX_TEXTURE_REGIONS, TextureRegionRaw::zeroed());
+        regions
+    }
+}
@@ -0,0 +1,29 @@
+/// Keeps frames from starting more often than the frame rate cap allows
+#[derive(Debug, Clone, Copy)]
+pub struct FrameLimiter {
+    /// The shortest time between the starts of two frames, in milliseconds
+    interval: Option<f64>,
+    /// When the next frame can start, in milliseconds
+    next_frame: f64,
+}
+
+impl FrameLimiter {
+    pub fn new(max_frame_rate: Option<f64>) -> Self {
+        Self {
+            interval: max_frame_rate.filter(|&rate| rate > 0.).map(|rate| 1000. / rate),
+            next_frame: 0.,
+        }
+    }
+    /// How many milliseconds to wait before the next frame can start
+    pub fn wait(&self, now: f64) -> f64 {
+        (self.next_frame - now).max(0.)
+    }
+    /// Call this at the start of every frame, with the current time in
+    /// milliseconds. Frames that start late push the next one back, rather
+    /// than letting the frames after them catch up.
+    pub fn begin_frame(&mut self, now: f64) {
+        if let Some(interval) = self.interval {
+            self.next_frame = (self.next_frame + interval).max(now);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::{borrow::Cow, error::Error, mem};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::{*, util::{BufferInitDescriptor, DeviceExt}};
+
+use crate::{
+    platform,
+    simulation::{HUE_SPEED, MAX_TIME_STEP},
+    square::{SquareInstance, SquareInstanceRaw},
+};
+
+/// Has to match the workgroup size in simulate.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+/// A flare, as the compute shader sees it
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct FlareRaw {
+    pos: [f32; 2],
+    vel: [f32; 2],
+    hue: f32,
+    index: u32,
+}
+
+impl From<SquareInstance> for FlareRaw {
+    fn from(value: SquareInstance) -> Self {
+        Self {
+            pos: value.pos.into(),
+            vel: value.vel.into(),
+            hue: value.hue,
+            index: value.index,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct SimulationParams {
+    dt: f32,
+    hue_speed: f32,
+    count: u32,
+    _padding: u32,
+}
+
+/// Whether the device can run the simulation on the GPU. Devices created
+/// with `Limits::downlevel_webgl2_defaults` can't run compute shaders at all.
+pub fn supported(device: &Device) -> bool {
+    let limits = device.limits();
+    limits.max_compute_workgroups_per_dimension > 0 &&
+    limits.max_compute_invocations_per_workgroup >= WORKGROUP_SIZE &&
+    limits.max_storage_buffers_per_shader_stage >= 2
+}
+
+/// The most flares that fit in the simulation's storage buffers on this
+/// device
+pub fn max_instance_count(device: &Device) -> u32 {
+    let limits = device.limits();
+    let bytes = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+    let per_flare = mem::size_of::<FlareRaw>().max(mem::size_of::<SquareInstanceRaw>()) as u64;
+    (bytes / per_flare).min(u32::MAX as u64) as u32
+}
+
+/// Moves the flares around with a compute shader, which writes straight into
+/// the instance buffer, so nothing has to be uploaded every frame.
+pub struct GpuSimulation {
+    pipeline: ComputePipeline,
+    bind_group_layout: BindGroupLayout,
+    params_buffer: Buffer,
+    instance_buffer: Buffer,
+    bind_group: BindGroup,
+    count: u32,
+}
+
+fn storage_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+impl GpuSimulation {
+    pub async fn new(device: &Device, instances: &[SquareInstance]) -> Result<Self, Box<dyn Error>> {
+        let shader_code = Cow::from(platform::read_text_asset("assets/simulate.wgsl").await?);
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Simulation shader module"),
+            source: ShaderSource::Wgsl(shader_code),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Simulation bindings (layout)"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Pipeline for simulating flares (layout)"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Pipeline for simulating flares"),
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: "simulate",
+        });
+        let params_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Simulation parameters"),
+            size: mem::size_of::<SimulationParams>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (instance_buffer, bind_group) = Self::create_buffers(device, &bind_group_layout, &params_buffer, instances);
+        Ok(Self {
+            pipeline,
+            bind_group_layout,
+            params_buffer,
+            instance_buffer,
+            bind_group,
+            count: instances.len() as u32,
+        })
+    }
+    fn create_buffers(
+        device: &Device,
+        layout: &BindGroupLayout,
+        params_buffer: &Buffer,
+        instances: &[SquareInstance],
+    ) -> (Buffer, BindGroup) {
+        let mut flares: Vec<FlareRaw> = instances.iter().copied().map(FlareRaw::from).collect();
+        let mut instance_data: Vec<SquareInstanceRaw> = instances.iter().copied().map(SquareInstanceRaw::from).collect();
+        // Storage buffers can't be empty
+        if instances.is_empty() {
+            flares.push(Zeroable::zeroed());
+            instance_data.push(Zeroable::zeroed());
+        }
+        let flare_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Simulated flares"),
+            contents: bytemuck::cast_slice(&flares),
+            usage: BufferUsages::STORAGE,
+        });
+        let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Square instance buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: BufferUsages::VERTEX | BufferUsages::STORAGE,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Simulation bindings"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: flare_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        (instance_buffer, bind_group)
+    }
+    /// Start over with a different set of flares
+    pub fn set_instances(&mut self, device: &Device, instances: &[SquareInstance]) {
+        (self.instance_buffer, self.bind_group) =
+            Self::create_buffers(device, &self.bind_group_layout, &self.params_buffer, instances);
+        self.count = instances.len() as u32;
+    }
+    /// The instance buffer, for the square pipeline to draw from
+    pub fn instance_buffer(&self) -> &Buffer {
+        &self.instance_buffer
+    }
+    /// Record a compute pass that moves the flares along by `dt` seconds
+    pub fn step(&self, device: &Device, queue: &Queue, commands: &mut CommandEncoder, dt: f32) {
+        if self.count == 0 {
+            return;
+        }
+        let params = SimulationParams {
+            dt: dt.clamp(0., MAX_TIME_STEP),
+            hue_speed: HUE_SPEED,
+            count: self.count,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+        // Spread the workgroups over a second dimension if there are too many
+        // to fit in one
+        let workgroups = self.count.div_ceil(WORKGROUP_SIZE);
+        let columns = workgroups.min(device.limits().max_compute_workgroups_per_dimension);
+        let rows = workgroups.div_ceil(columns);
+        let mut compute_pass = commands.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Simulation pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(columns, rows, 1);
+    }
+}
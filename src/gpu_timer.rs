@@ -0,0 +1,176 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+use wgpu::*;
+
+/// How many frames can be waiting for their timestamps to be read back
+/// before we start skipping frames rather than waiting.
+const RING_SIZE: u32 = 4;
+/// Each frame has a timestamp before and after the render pass
+const QUERIES_PER_FRAME: u32 = 2;
+const TIMESTAMP_SIZE: BufferAddress = std::mem::size_of::<u64>() as BufferAddress;
+
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+enum SlotState {
+    Free,
+    /// The timestamps have been written to the command encoder, but the
+    /// commands haven't been submitted yet.
+    Recorded { frame: u32, generation: u32 },
+    /// Waiting for the readback buffer to be mapped
+    Mapping { frame: u32, generation: u32, status: Arc<AtomicU8> },
+}
+
+struct ReadbackSlot {
+    buffer: Buffer,
+    state: SlotState,
+}
+
+/// Measures how long the GPU takes to run a render pass, using timestamp
+/// queries. Results are read back asynchronously, a few frames later, so the
+/// frame loop never has to wait for the GPU.
+pub struct GpuTimer {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    slots: Vec<ReadbackSlot>,
+    next_slot: usize,
+    /// Goes up whenever the frame numbers start over, so that times for
+    /// frames from before then can be thrown away
+    generation: u32,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+}
+
+impl GpuTimer {
+    /// Returns `None` if the device can't do timestamp queries
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("Render pass timestamps"),
+            ty: QueryType::Timestamp,
+            count: RING_SIZE * QUERIES_PER_FRAME,
+        });
+        // Query resolve offsets have to be aligned, so each slot gets its own
+        // aligned section of the resolve buffer.
+        let resolve_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Render pass timestamp resolve buffer"),
+            size: QUERY_RESOLVE_BUFFER_ALIGNMENT * RING_SIZE as BufferAddress,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let slots = (0..RING_SIZE).map(|_| ReadbackSlot {
+            buffer: device.create_buffer(&BufferDescriptor {
+                label: Some("Render pass timestamp readback buffer"),
+                size: TIMESTAMP_SIZE * QUERIES_PER_FRAME as BufferAddress,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            state: SlotState::Free,
+        }).collect();
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            slots,
+            next_slot: 0,
+            generation: 0,
+            period: queue.get_timestamp_period(),
+        })
+    }
+    /// Record the commands issued by `encode` and time them. If every
+    /// readback slot is still busy, the commands are recorded without being
+    /// timed.
+    pub fn time(&mut self, commands: &mut CommandEncoder, frame: u32, encode: impl FnOnce(&mut CommandEncoder)) {
+        let slot_index = self.next_slot;
+        let slot = &mut self.slots[slot_index];
+        if !matches!(slot.state, SlotState::Free) {
+            encode(commands);
+            return;
+        }
+        let first_query = slot_index as u32 * QUERIES_PER_FRAME;
+        commands.write_timestamp(&self.query_set, first_query);
+        encode(commands);
+        commands.write_timestamp(&self.query_set, first_query + 1);
+        let resolve_offset = QUERY_RESOLVE_BUFFER_ALIGNMENT * slot_index as BufferAddress;
+        commands.resolve_query_set(
+            &self.query_set,
+            first_query..first_query + QUERIES_PER_FRAME,
+            &self.resolve_buffer,
+            resolve_offset,
+        );
+        commands.copy_buffer_to_buffer(
+            &self.resolve_buffer, resolve_offset,
+            &slot.buffer, 0,
+            slot.buffer.size(),
+        );
+        slot.state = SlotState::Recorded { frame, generation: self.generation };
+        self.next_slot = (self.next_slot + 1) % self.slots.len();
+    }
+    /// Start reading back the timestamps recorded since the last submission.
+    /// Call this after the commands have been submitted. `on_read_back` is
+    /// called once they're ready to be collected, which happens when the
+    /// device is polled, or the next commands are submitted.
+    pub fn after_submit(&mut self, on_read_back: impl Fn() + Clone + WasmNotSend + 'static) {
+        self.slots.iter_mut().for_each(|slot| {
+            if let SlotState::Recorded { frame, generation } = slot.state {
+                let status = Arc::new(AtomicU8::new(MAP_PENDING));
+                let callback_status = Arc::clone(&status);
+                let on_read_back = on_read_back.clone();
+                slot.buffer.slice(..).map_async(MapMode::Read, move |result| {
+                    let status = if result.is_ok() { MAP_DONE } else { MAP_FAILED };
+                    callback_status.store(status, Ordering::Release);
+                    on_read_back();
+                });
+                slot.state = SlotState::Mapping { frame, generation, status };
+            }
+        });
+    }
+    /// Throw away the times of the frames that have already been timed, once
+    /// they're read back. Call this when the frame numbers start over, so
+    /// that old times aren't taken for the new frames with the same numbers.
+    pub fn discard_pending(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+    /// Collect the render pass times, in milliseconds, of the frames whose
+    /// timestamps have been read back, and haven't been discarded.
+    pub fn collect(&mut self) -> Vec<(u32, f64)> {
+        let period = self.period as f64;
+        self.slots.iter_mut().filter_map(|slot| {
+            let SlotState::Mapping { frame, generation, ref status } = slot.state else {
+                return None;
+            };
+            match status.load(Ordering::Acquire) {
+                MAP_PENDING => return None,
+                MAP_FAILED => {
+                    // Free the slot up again, but there's nothing to read
+                    slot.state = SlotState::Free;
+                    return None;
+                }
+                _ => (),
+            }
+            let time = {
+                let view = slot.buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&view);
+                // Timestamps can wrap around, or come out of order on some
+                // drivers.
+                timestamps[1].checked_sub(timestamps[0])
+                    .map(|ticks| ticks as f64 * period / 1_000_000.)
+            };
+            slot.buffer.unmap();
+            slot.state = SlotState::Free;
+            time.filter(|_| generation == self.generation).map(|time| (frame, time))
+        }).collect()
+    }
+    /// Wait for all the outstanding timestamps to be read back, and collect
+    /// them. Only headless runs wait like this, and they're native only.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn finish(&mut self, device: &Device) -> Vec<(u32, f64)> {
+        device.poll(Maintain::Wait);
+        self.collect()
+    }
+}
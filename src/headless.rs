@@ -0,0 +1,116 @@
+use std::error::Error;
+
+use wgpu::*;
+
+use crate::{
+    config::BenchConfig,
+    platform,
+    gpu_timer::GpuTimer,
+    report::RunInfo,
+    scene::Scene,
+    stats::FrameStats,
+    util::{
+        context::{create_instance, GpuContext, GpuContextOptions},
+        offscreen::OffscreenTarget,
+    },
+};
+
+/// How many frames to render if neither a frame count nor a duration is
+/// given
+pub const HEADLESS_FRAMES: u32 = 1000;
+
+/// Like `AppState`, but renders into an offscreen texture, so it doesn't need
+/// a window, a surface, or a display.
+pub struct HeadlessState {
+    pub context: GpuContext,
+    target: OffscreenTarget,
+    scene: Scene,
+    pub stats: FrameStats,
+    gpu_timer: Option<GpuTimer>,
+}
+
+impl HeadlessState {
+    pub async fn setup(config: &BenchConfig) -> Result<HeadlessState, Box<dyn Error>> {
+        // Build machines usually don't have a GPU, so try the software adapter
+        // first, and settle for whatever else is available if there isn't one.
+        let options = GpuContextOptions::from_config(config, true);
+        let instance = create_instance(options.backends);
+        let context = GpuContext::new(instance, &options, None).await?;
+        let gpu_timer = GpuTimer::new(&context.device, &context.queue);
+        let (width, height) = (config.width, config.height);
+        let target = OffscreenTarget::new(&context, width, height, config.srgb, config.sample_count);
+        let scene = Scene::new(&context, target.format(),
+            target.sample_count(), (width, height), config).await?;
+        Ok(HeadlessState {
+            context,
+            target,
+            scene,
+            stats: FrameStats::new(config.warmup_frames),
+            gpu_timer,
+        })
+    }
+    pub fn set_instance_count(&mut self, count: u32) {
+        self.scene.set_instance_count(&self.context.device, count);
+    }
+    /// The most instances the device can draw
+    pub fn max_instance_count(&self) -> u32 {
+        self.scene.max_instance_count(&self.context.device)
+    }
+    pub fn run_info(&self) -> RunInfo {
+        RunInfo::new(
+            &self.context.adapter_info,
+            self.target.format(),
+            self.target.sample_count(),
+            None,
+            &self.scene,
+            self.target.size(),
+        )
+    }
+    pub fn render(&mut self) {
+        let now = platform::now();
+        self.stats.begin_frame(now);
+        self.scene.update(now);
+        let mut commands = self
+            .context
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("My commands"),
+            });
+        // Upload outside of the timed section, so that the copies don't count
+        // towards the render pass time
+        if let Some(time) = self.scene.upload(&self.context.device, &self.context.queue, &mut commands) {
+            self.stats.record_upload_time(self.stats.frame(), time);
+        }
+        self.scene.simulate(&self.context.device, &self.context.queue, &mut commands);
+        let target = self.target.render_target();
+        match self.gpu_timer.as_mut() {
+            Some(timer) => timer.time(&mut commands, self.stats.frame(), |commands| {
+                self.scene.encode(commands, &target);
+            }),
+            None => self.scene.encode(&mut commands, &target),
+        }
+        let submission = self.context.queue.submit([commands.finish()]);
+        self.scene.after_submit();
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            // The times are collected after waiting for the frame below
+            timer.after_submit(|| ());
+        }
+        // There is no swapchain to throttle us, so wait for the GPU to finish
+        // the frame before starting the next one.
+        self.context.device.poll(Maintain::WaitForSubmissionIndex(submission));
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            for (frame, time) in timer.collect() {
+                self.stats.record_gpu_time(frame, time);
+            }
+        }
+    }
+    /// Wait for the GPU to finish, and record any GPU times that haven't been
+    /// read back yet.
+    pub fn finish(&mut self) {
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            for (frame, time) in timer.finish(&self.context.device) {
+                self.stats.record_gpu_time(frame, time);
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::f32::consts::TAU;
+
+use glam::Vec2;
+
+use crate::square::SquareInstance;
+
+pub const DEFAULT_SEED: u64 = 0x5EED_F1A2E;
+/// Distance between rings, in clip space units, so that `NUM_RINGS` rings fit
+/// on the screen.
+pub const DEFAULT_RING_SPACING: f32 = 1. / crate::NUM_RINGS as f32;
+/// Slowest and fastest a flare can move, in clip space units per second
+const MIN_SPEED: f32 = 0.05;
+const MAX_SPEED: f32 = 0.25;
+
+/// How the flares are arranged on the screen. Every layout is generated from
+/// a seed, so the same seed always gives the same scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// Concentric rings around the middle of the screen. Flares go on the
+    /// innermost ring first, and once a ring has `per_ring` flares, the next
+    /// ring out is started. Once all `rings` rings are full, the flares go
+    /// around them again, starting from the innermost ring.
+    Rings {
+        rings: u32,
+        /// If this is `None`, the flares are spread evenly over `rings` rings.
+        per_ring: Option<u32>,
+        /// Distance between rings
+        spacing: f32,
+        /// How many times the hue goes around the colour wheel on each ring
+        hue_turns: f32,
+    },
+    /// A square grid covering the screen
+    Grid,
+    /// Uniformly distributed over the screen
+    UniformRandom,
+    /// Normally distributed around a few randomly placed points
+    GaussianCluster {
+        clusters: u32,
+        /// Standard deviation of the distance from the centre of the cluster
+        spread: f32,
+    },
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Rings {
+            rings: crate::NUM_RINGS as u32,
+            per_ring: None,
+            spacing: DEFAULT_RING_SPACING,
+            hue_turns: 1.,
+        }
+    }
+}
+
+/// SplitMix64. Small, fast, and good enough for scattering flares around.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    /// Uniformly distributed in [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+    fn range(&mut self, low: f32, high: f32) -> f32 {
+        low + (high - low) * self.next_f32()
+    }
+    /// A pair of independent standard normal samples (Box-Muller)
+    fn normal2(&mut self) -> Vec2 {
+        let radius = (-2. * (1. - self.next_f32()).ln()).sqrt();
+        Vec2::from_angle(self.next_f32() * TAU) * radius
+    }
+}
+
+impl Layout {
+    pub fn generate(&self, count: u32, seed: u64) -> Vec<SquareInstance> {
+        let mut rng = Rng(seed);
+        let instance = |index: u32, pos: Vec2, hue: f32| SquareInstance {
+            pos,
+            vel: Vec2::ZERO,
+            hue: hue.rem_euclid(TAU),
+            index,
+            texture: 0,
+        };
+        let mut instances: Vec<SquareInstance> = match *self {
+            Layout::Rings { rings, per_ring, spacing, hue_turns } => {
+                let rings = rings.max(1);
+                let per_ring = per_ring
+                    .unwrap_or_else(|| count.div_ceil(rings))
+                    .max(1);
+                // Rotate each ring by a random amount, so that the flares
+                // don't all line up, and so that going around the rings again
+                // doesn't put flares on top of each other.
+                let mut ring_rotation = Vec::new();
+                (0..count).map(|index| {
+                    let filled = (index / per_ring) as usize;
+                    let ring = filled as u32 % rings;
+                    let slot = index % per_ring;
+                    if ring_rotation.len() <= filled {
+                        ring_rotation.push(rng.range(0., TAU));
+                    }
+                    let turn = slot as f32 / per_ring as f32 * TAU;
+                    let radius = spacing * (ring + 1) as f32;
+                    let pos = Vec2::from_angle(turn + ring_rotation[filled]) * radius;
+                    instance(index, pos, turn * hue_turns)
+                }).collect()
+            }
+            Layout::Grid => {
+                let columns = (count as f32).sqrt().ceil().max(1.) as u32;
+                let rows = count.div_ceil(columns).max(1);
+                let cell = Vec2::new(2. / columns as f32, 2. / rows as f32);
+                (0..count).map(|index| {
+                    let column = index % columns;
+                    let row = index / columns;
+                    let pos = Vec2::new(column as f32 + 0.5, row as f32 + 0.5) * cell - Vec2::ONE;
+                    instance(index, pos, index as f32 / count as f32 * TAU)
+                }).collect()
+            }
+            Layout::UniformRandom => (0..count).map(|index| {
+                let pos = Vec2::new(rng.range(-1., 1.), rng.range(-1., 1.));
+                instance(index, pos, rng.range(0., TAU))
+            }).collect(),
+            Layout::GaussianCluster { clusters, spread } => {
+                let centres: Vec<(Vec2, f32)> = (0..clusters.max(1)).map(|_| {
+                    let centre = Vec2::new(rng.range(-0.75, 0.75), rng.range(-0.75, 0.75));
+                    (centre, rng.range(0., TAU))
+                }).collect();
+                (0..count).map(|index| {
+                    let (centre, hue) = centres[index as usize % centres.len()];
+                    let offset = rng.normal2() * spread;
+                    // Flares in the same cluster have similar hues
+                    instance(index, centre + offset, hue + offset.x)
+                }).collect()
+            }
+        };
+        // Velocities come after the positions, so that the positions stay the
+        // same for a given seed whether or not the flares are moving.
+        instances.iter_mut().for_each(|instance| {
+            instance.vel = Vec2::from_angle(rng.range(0., TAU)) * rng.range(MIN_SPEED, MAX_SPEED);
+        });
+        instances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [Layout; 4] = [
+        Layout::Rings { rings: 4, per_ring: Some(10), spacing: 0.2, hue_turns: 1. },
+        Layout::Grid,
+        Layout::UniformRandom,
+        Layout::GaussianCluster { clusters: 3, spread: 0.1 },
+    ];
+
+    #[test]
+    fn same_seed_same_layout() {
+        for layout in LAYOUTS {
+            assert_eq!(layout.generate(100, 7), layout.generate(100, 7), "{layout:?}");
+            assert_ne!(layout.generate(100, 7), layout.generate(100, 8), "{layout:?}");
+        }
+    }
+
+    #[test]
+    fn rings_stay_within_ring_count() {
+        let layout = Layout::Rings { rings: 4, per_ring: Some(10), spacing: 0.2, hue_turns: 1. };
+        let instances = layout.generate(100, DEFAULT_SEED);
+        assert!(instances.iter().all(|instance| instance.pos.length() <= 0.8 + 1e-5));
+    }
+}
@@ -1,29 +1,229 @@
 #[cfg(target_family = "wasm")]
 use wasm_bindgen::prelude::*;
-use winit::event::{Event, WindowEvent};
+use std::time::Duration;
+
+use winit::{
+    event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
+    event_loop::ControlFlow,
+};
 #[cfg(target_family = "wasm")]
 use winit::platform::web::WindowExtWebSys;
 
-const NUM_RINGS: usize = 15;
+pub const NUM_RINGS: usize = 15;
+/// How long to wait between checks on whether the run is finished, while the
+/// window is minimized or hidden, or while the last phase change is on its
+/// way
+const IDLE_WAIT: Duration = Duration::from_millis(100);
 
 mod app;
-use app::AppState;
+mod command;
+pub use command::Command;
+mod config;
+pub use config::{BenchConfig, DEFAULT_TEXTURES};
+mod flare_textures;
+mod frame_limiter;
+use frame_limiter::FrameLimiter;
+pub use flare_textures::TexturePacking;
+mod gpu_simulation;
+mod gpu_timer;
+use app::{AppEvent, AppState, Phase};
+#[cfg(not(target_family = "wasm"))]
+mod headless;
+mod layout;
+pub use layout::{Layout, DEFAULT_RING_SPACING, DEFAULT_SEED};
+#[cfg(not(target_family = "wasm"))]
+pub use headless::HEADLESS_FRAMES;
+#[cfg(not(target_family = "wasm"))]
+use headless::HeadlessState;
+mod report;
+use report::BenchReport;
+mod scene;
+mod stats;
+pub use stats::DEFAULT_WARMUP_FRAMES;
+mod sweep;
+pub use sweep::SweepConfig;
+use sweep::Sweep;
+mod simulation;
+pub use simulation::DEFAULT_UPDATE_RATE;
 mod staged_buffer;
+mod upload;
+pub use upload::UploadStrategy;
 mod util;
+pub use util::mipmap::MipGeneration;
+#[cfg(not(target_family = "wasm"))]
+pub use util::context::list_adapters;
 pub(crate) mod platform;
 
 mod square;
+pub use square::BlendMode;
 
 use crate::app::CreatedWindow;
 
-#[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
-pub async fn run() {
-    #[cfg(target_family = "wasm")]
-    {
-        console_error_panic_hook::set_once();
+#[cfg(target_family = "wasm")]
+#[wasm_bindgen(start)]
+pub async fn start() {
+    console_error_panic_hook::set_once();
+    if let Err(error) = run(BenchConfig::default()).await {
+        platform::log(&format!("Could not start the benchmark: {error}"));
+    }
+}
+
+/// Write the results of the run to the console and output files
+fn report(
+    info: report::RunInfo,
+    stats: &stats::FrameStats,
+    sweep: Option<&Sweep>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match sweep {
+        Some(sweep) => {
+            let sweep_report = sweep.report();
+            sweep_report.log();
+            sweep_report.write()
+        }
+        None => {
+            stats.log_report();
+            BenchReport::new(info, stats).write()
+        }
+    }
+}
+
+/// Render a frame. wgpu 0.17 panics when work is submitted to a lost device,
+/// so those panics are caught and treated as the device being lost.
+#[cfg(not(target_family = "wasm"))]
+fn render(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
+    use std::panic::{self, AssertUnwindSafe};
+    match panic::catch_unwind(AssertUnwindSafe(|| app.render())) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload.downcast_ref::<String>().map(String::as_str)
+                .or_else(|| payload.downcast_ref::<&str>().copied())
+                .unwrap_or("");
+            // wgpu 0.17 has no device lost callback, and `Queue::submit`,
+            // `Device::poll`, and `Surface::configure` panic instead of
+            // returning an error, so the panic message is all there is to go
+            // on. Newer versions of wgpu report a lost device with
+            // `Device::set_device_lost_callback`, which should replace this.
+            if !util::context::is_device_lost_error(message) {
+                panic::resume_unwind(payload);
+            }
+            app.set_device_lost();
+            Ok(())
+        }
+    }
+}
+
+/// Render a frame. Panics can't be caught on the web, so the device is only
+/// known to be lost if its error handler says so.
+#[cfg(target_family = "wasm")]
+fn render(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
+    app.render()
+}
+
+/// Make everything on the GPU again in the background, after the device was
+/// lost or the pipelines' settings were changed. The app comes back in
+/// `AppEvent::Rebuilt`.
+fn rebuild(mut app: Box<AppState>) {
+    let proxy = app.proxy();
+    platform::spawn(async move {
+        let rebuilt = app.rebuild().await.map_err(|error| error.to_string());
+        let _ = proxy.send_event(AppEvent::Rebuilt(app, rebuilt));
+    });
+}
+
+/// Write the results and stop the event loop. The exit code is 1 if the
+/// results can't be written, even if the run went well.
+fn finish(app: &AppState, control_flow: &mut ControlFlow, exit_code: i32) {
+    let exit_code = match report(app.run_info(), &app.stats, app.sweep.as_ref()) {
+        Ok(()) => exit_code,
+        Err(error) => {
+            platform::log(&format!("Could not write benchmark results: {error}"));
+            1
+        }
+    };
+    control_flow.set_exit_with_code(exit_code);
+}
+
+/// Deal with an event sent through the `EventLoopProxy`. `app` is `None`
+/// until the app is loaded, and while it's being rebuilt.
+fn handle_app_event(app: &mut Option<Box<AppState>>, event: AppEvent, control_flow: &mut ControlFlow) {
+    match event {
+        AppEvent::Loaded(Ok(loaded)) => *app = Some(loaded),
+        AppEvent::Loaded(Err(error)) => {
+            platform::log(&format!("Could not set up the app: {error}"));
+            control_flow.set_exit_with_code(1);
+        }
+        AppEvent::Rebuilt(rebuilt, Ok(())) => *app = Some(rebuilt),
+        AppEvent::Rebuilt(rebuilt, Err(error)) => {
+            platform::log(&format!("Could not rebuild the scene: {error}"));
+            finish(&rebuilt, control_flow, 1);
+        }
+        // Anything else is about the running app, so it's dropped if the
+        // app isn't there
+        event => if let Some(app) = app.as_deref_mut() {
+            match event {
+                AppEvent::PhaseChanged(Phase::Finished) => finish(app, control_flow, 0),
+                AppEvent::PhaseChanged(phase) => platform::log(&format!("Phase: {phase:?}")),
+                AppEvent::ScreenshotReady(mapped) => app.save_screenshot(mapped),
+                AppEvent::GpuTimesReady => app.collect_gpu_times(),
+                AppEvent::Command(Command::Quit) => finish(app, control_flow, 0),
+                AppEvent::Command(command) => app.run_command(command),
+                AppEvent::Loaded(_) | AppEvent::Rebuilt(..) => (),
+            }
+        }
+    }
+}
+
+/// Start rebuilding the app if it needs it. It's taken out of `app` until
+/// it's been rebuilt.
+fn rebuild_if_needed(app: &mut Option<Box<AppState>>) {
+    if app.as_deref().is_some_and(AppState::needs_rebuild) {
+        if let Some(app) = app.take() {
+            rebuild(app);
+        }
+    }
+}
+
+/// The command for a key, if it has one:
+///
+/// - P: next present mode
+/// - B: next blend mode
+/// - M: next MSAA sample count
+/// - Up or +: twice as many instances
+/// - Down or -: half as many instances
+/// - Space: pause or unpause
+/// - Period: move one step while paused
+/// - R: reset the layout
+/// - Home: reset the view after panning and zooming
+/// - F12: screenshot
+/// - Escape: quit
+///
+/// The view can also be panned by dragging with the left mouse button, and
+/// zoomed with the mouse wheel.
+fn key_command(key: VirtualKeyCode) -> Option<Command> {
+    match key {
+        VirtualKeyCode::P => Some(Command::NextPresentMode),
+        VirtualKeyCode::B => Some(Command::NextBlendMode),
+        VirtualKeyCode::M => Some(Command::NextSampleCount),
+        VirtualKeyCode::Up | VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd =>
+            Some(Command::MoreInstances),
+        VirtualKeyCode::Down | VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract =>
+            Some(Command::FewerInstances),
+        VirtualKeyCode::Space => Some(Command::TogglePause),
+        VirtualKeyCode::Period => Some(Command::Step),
+        VirtualKeyCode::R => Some(Command::ResetLayout),
+        VirtualKeyCode::Home => Some(Command::ResetView),
+        VirtualKeyCode::F12 => Some(Command::Screenshot),
+        VirtualKeyCode::Escape => Some(Command::Quit),
+        _ => None,
     }
-    let CreatedWindow { window, event_loop } =
-        app::create_window().expect("Could not create window");
+}
+
+/// Open a window and render into it continuously, as fast as the present
+/// mode and frame rate cap allow, until the run is finished or the window is
+/// closed. Only returns if the window can't be opened. Once it's running, the
+/// process exits with a non-zero code if something goes wrong.
+pub async fn run(config: BenchConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let CreatedWindow { window, event_loop } = app::create_window(config.width, config.height)?;
     let elproxy = event_loop.create_proxy();
     #[cfg(target_family = "wasm")]
     {
@@ -33,34 +233,143 @@ pub async fn run() {
         let bod = doc.body().expect("No body!");
         bod.append_child(&canvas)
             .expect("Could not add canvas to document");
+        command::set_proxy(elproxy.clone());
     }
+    #[cfg(not(target_family = "wasm"))]
+    command::read_stdin(elproxy.clone());
     let primary_id = window.id();
-    let mut app = AppState::setup(window, elproxy)
-        .await
-        .expect("Could not set up app");
-    app.window.request_redraw();
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::WindowEvent { window_id, event } => {
-            if window_id == primary_id {
+    // On the web, loading the assets means waiting for them to be fetched,
+    // so the app is set up in the background, and handed over with
+    // AppEvent::Loaded.
+    platform::spawn(async move {
+        let loaded = AppState::setup(window, elproxy.clone(), config).await
+            .map(Box::new)
+            .map_err(|error| error.to_string());
+        let _ = elproxy.send_event(AppEvent::Loaded(loaded));
+    });
+    let mut app: Option<Box<AppState>> = None;
+    event_loop.run(move |event, _, control_flow| {
+        // Events can still arrive after the run has finished
+        if let ControlFlow::ExitWithCode(_) = control_flow {
+            return;
+        }
+        platform::run_tasks();
+        if let Event::UserEvent(event) = event {
+            handle_app_event(&mut app, event, control_flow);
+            if !matches!(control_flow, ControlFlow::ExitWithCode(_)) {
+                rebuild_if_needed(&mut app);
+            }
+            return;
+        }
+        let Some(state) = app.as_deref_mut() else {
+            // Wait for the app to be loaded or rebuilt
+            control_flow.set_wait();
+            return;
+        };
+        match event {
+            Event::WindowEvent { window_id, event } if window_id == primary_id => {
                 match event {
-                    WindowEvent::CloseRequested => {
-                        control_flow.set_exit_with_code(0);
-                    }
-                    WindowEvent::Resized(new_size) => {
-                        app.window.request_redraw();
-                        app.resize(new_size);
-                    }
+                    WindowEvent::CloseRequested => finish(state, control_flow, 0),
+                    WindowEvent::Resized(new_size) => state.resize(new_size),
+                    WindowEvent::Occluded(occluded) => state.set_occluded(occluded),
+                    WindowEvent::KeyboardInput {
+                        input: KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                        ..
+                    } => match key_command(key) {
+                        Some(Command::Quit) => finish(state, control_flow, 0),
+                        Some(command) => state.run_command(command),
+                        None => (),
+                    },
+                    WindowEvent::MouseInput { state: button_state, button: MouseButton::Left, .. } =>
+                        state.mouse_button(button_state),
+                    WindowEvent::CursorMoved { position, .. } => state.cursor_moved(position),
+                    WindowEvent::CursorLeft { .. } => state.cursor_left(),
+                    WindowEvent::MouseWheel { delta, .. } => state.mouse_wheel(delta),
                     _ => (),
                 }
             }
-        }
-        Event::RedrawRequested(window_id) => {
-            if window_id == primary_id {
-                if let Err(error) = app.render() {
-                    eprintln!("{error:?}");
+            Event::MainEventsCleared => {
+                // Checked here, as well as after each frame, so that a run
+                // with a duration still ends while the window is minimized
+                state.update_phase();
+                let wait = state.time_until_next_frame();
+                if state.phase() == Phase::Finished || !state.can_render() {
+                    control_flow.set_wait_timeout(IDLE_WAIT);
+                } else if wait > 0. {
+                    control_flow.set_wait_timeout(Duration::from_secs_f64(wait / 1000.));
+                } else {
+                    control_flow.set_poll();
+                    state.window.request_redraw();
                 }
             }
+            Event::RedrawRequested(window_id) if window_id == primary_id => {
+                if let Err(error) = render(state) {
+                    platform::log(&format!("Stopping the run: {error}"));
+                    finish(state, control_flow, 1);
+                } else if state.device_lost() {
+                    platform::log("Rebuilding everything on a new device");
+                } else {
+                    state.update_phase();
+                }
+            }
+            _ => (),
         }
-        _ => (),
-    });
+        // After a command that changes the pipelines, or a lost device
+        if !matches!(control_flow, ControlFlow::ExitWithCode(_)) {
+            rebuild_if_needed(&mut app);
+        }
+    })
+}
+
+/// Render the benchmark scene offscreen, without creating a window. If the
+/// config doesn't say when to stop, and there's no sweep to finish,
+/// `HEADLESS_FRAMES` frames are rendered. Only on native, since the frame
+/// rate cap is kept by putting the thread to sleep.
+#[cfg(not(target_family = "wasm"))]
+pub async fn run_headless(mut config: BenchConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if config.frames.is_none() && config.duration.is_none() && config.sweep.is_none() {
+        config.frames = Some(HEADLESS_FRAMES);
+    }
+    let mut app = HeadlessState::setup(&config).await?;
+    println!(
+        "wgpu backend: {:?} ({})",
+        app.context.adapter_info.backend, app.context.adapter_info.name
+    );
+    let mut sweep = config.sweep.map(|sweep| Sweep::new(sweep, app.max_instance_count()));
+    if let Some(sweep) = &sweep {
+        app.set_instance_count(sweep.instance_count());
+    }
+    let mut frame_limiter = FrameLimiter::new(config.max_frame_rate);
+    let start_time = platform::now();
+    let mut frames = 0;
+    while !config.finished(frames, platform::now() - start_time) {
+        let wait = frame_limiter.wait(platform::now());
+        if wait > 0. {
+            std::thread::sleep(Duration::from_secs_f64(wait / 1000.));
+        }
+        frame_limiter.begin_frame(platform::now());
+        if let Some(sweep) = sweep.as_mut() {
+            if let Some(count) = sweep.begin_frame(platform::now()) {
+                app.set_instance_count(count);
+            }
+            if sweep.done() {
+                break;
+            }
+        }
+        if app.context.device_lost() {
+            return Err(Box::from("The device was lost"));
+        }
+        app.render();
+        frames += 1;
+    }
+    // Frame times are measured from the start of one frame to the start of
+    // the next, so one more is needed to finish timing the last frame.
+    app.stats.begin_frame(platform::now());
+    app.finish();
+    println!("Rendered {frames} frames");
+    report(app.run_info(), &app.stats, sweep.as_ref())
 }
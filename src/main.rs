@@ -1,5 +1,415 @@
-use futures::executor;
+// The web build starts from `wgpubench::start` instead, and clap is only a
+// dependency on native
+#[cfg(not(target_family = "wasm"))]
+mod native {
+    use clap::{Args, Parser, Subcommand, ValueEnum};
+    use futures::executor;
+    use wgpu::{Backends, PowerPreference, PresentMode};
+    use wgpubench::{BenchConfig, BlendMode, Layout, MipGeneration, SweepConfig, TexturePacking, UploadStrategy};
 
+    #[derive(Parser)]
+    #[command(about = "Benchmarks wgpu by rendering lots of lens flares")]
+    #[command(args_conflicts_with_subcommands = true)]
+    struct Cli {
+        #[command(subcommand)]
+        command: Option<Command>,
+        /// Options for `run`, which is what happens if no subcommand is given
+        #[command(flatten)]
+        run: RunArgs,
+    }
+
+    #[derive(Subcommand)]
+    enum Command {
+        /// List the adapters available on this machine
+        ListAdapters {
+            /// Comma-separated list of backends to look at, or "all"
+            #[arg(long, default_value = "all", value_parser = parse_backends)]
+            backend: Backends,
+        },
+        /// Run the benchmark
+        Run(RunArgs),
+        /// Find the largest instance count that fits in a frame time budget
+        Sweep {
+            #[command(flatten)]
+            sweep: SweepArgs,
+            #[command(flatten)]
+            run: RunArgs,
+        },
+    }
+
+    #[derive(Args)]
+    struct SweepArgs {
+        /// Frame time budget, in milliseconds
+        #[arg(long, default_value_t = 1000. / 60.)]
+        budget: f64,
+        /// Instance count to start with
+        #[arg(long, default_value_t = 1)]
+        start: u32,
+        /// Don't go past this many instances, or the most the device can draw,
+        /// whichever is fewer
+        #[arg(long, default_value_t = 1 << 24)]
+        max: u32,
+        /// Frames to measure at each instance count
+        #[arg(long, default_value_t = 60)]
+        frames_per_step: u32,
+        /// Frames to skip after changing the instance count
+        #[arg(long, default_value_t = 10)]
+        step_warmup_frames: u32,
+    }
+
+    impl From<SweepArgs> for SweepConfig {
+        fn from(value: SweepArgs) -> Self {
+            SweepConfig {
+                start: value.start,
+                max: value.max,
+                budget: value.budget,
+                warmup_frames: value.step_warmup_frames,
+                frames_per_step: value.frames_per_step,
+            }
+        }
+    }
+
+    #[derive(Args)]
+    struct RunArgs {
+        /// Render offscreen, without a window
+        #[arg(long)]
+        headless: bool,
+        #[arg(long, default_value_t = 640)]
+        width: u32,
+        #[arg(long, default_value_t = 480)]
+        height: u32,
+        /// Comma-separated list of backends to choose from (vulkan, metal, dx12,
+        /// dx11, gl, webgpu), or "all"
+        #[arg(long, default_value = "all", value_parser = parse_backends)]
+        backend: Backends,
+        #[arg(long, value_enum, default_value_t = PowerArg::High)]
+        power: PowerArg,
+        /// Try the fallback (software) adapter before any hardware adapters.
+        /// Defaults to true for headless runs, and false otherwise.
+        #[arg(long)]
+        fallback_adapter: Option<bool>,
+        /// How frames are presented. Press P in the window to switch between the
+        /// supported present modes.
+        #[arg(long, value_enum, default_value_t = PresentModeArg::AutoNoVsync)]
+        present_mode: PresentModeArg,
+        /// Render to a non-sRGB view of the output, so it isn't gamma-encoded
+        #[arg(long)]
+        linear_output: bool,
+        /// MSAA sample count. Lowered if the adapter doesn't support it.
+        #[arg(long, default_value_t = 1, value_parser = parse_sample_count)]
+        msaa: u32,
+        /// Don't use alpha-to-coverage when multisampling
+        #[arg(long)]
+        no_alpha_to_coverage: bool,
+        /// How to blend the flares. Give a comma-separated list to split the
+        /// flares into layers, each with its own blend mode.
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "alpha")]
+        blend: Vec<BlendArg>,
+        /// How many flares to draw
+        #[arg(long, default_value_t = 2)]
+        instances: u32,
+        /// How to arrange the flares
+        #[arg(long, value_enum, default_value_t = LayoutArg::Rings)]
+        layout: LayoutArg,
+        /// Seed for the random parts of the layout
+        #[arg(long, default_value_t = wgpubench::DEFAULT_SEED)]
+        seed: u64,
+        /// Keep the flares still, so the instance buffer is only uploaded once
+        #[arg(long)]
+        no_animation: bool,
+        /// How to get the moving flares to the GPU every frame
+        #[arg(long, value_enum, default_value_t = UploadArg::Staged)]
+        upload: UploadArg,
+        /// Move the flares on the CPU, even if the GPU can run compute shaders
+        #[arg(long)]
+        cpu_simulation: bool,
+        /// How many times a second to move the flares, however fast the frames
+        /// are rendered
+        #[arg(long, default_value_t = wgpubench::DEFAULT_UPDATE_RATE, value_parser = parse_rate)]
+        update_rate: f64,
+        /// Number of rings to spread the flares over (rings layout)
+        #[arg(long, default_value_t = wgpubench::NUM_RINGS as u32)]
+        rings: u32,
+        /// Flares per ring, instead of spreading them evenly (rings layout)
+        #[arg(long)]
+        per_ring: Option<u32>,
+        /// Distance between rings, in clip space (rings layout)
+        #[arg(long, default_value_t = wgpubench::DEFAULT_RING_SPACING)]
+        ring_spacing: f32,
+        /// How many times the hue goes around the colour wheel on each ring
+        /// (rings layout)
+        #[arg(long, default_value_t = 1.)]
+        hue_turns: f32,
+        /// Number of clusters (gaussian layout)
+        #[arg(long, default_value_t = 8)]
+        clusters: u32,
+        /// Standard deviation of each cluster, in clip space (gaussian layout)
+        #[arg(long, default_value_t = 0.1)]
+        cluster_spread: f32,
+        /// Comma-separated list of flare textures. The flares take turns using
+        /// each of them.
+        #[arg(long, value_delimiter = ',', default_values = wgpubench::DEFAULT_TEXTURES)]
+        textures: Vec<String>,
+        /// How to put the flare textures together
+        #[arg(long, value_enum, default_value_t = PackingArg::Array)]
+        texture_packing: PackingArg,
+        /// Where the flare textures' mips are made
+        #[arg(long, value_enum, default_value_t = MipArg::Gpu)]
+        mip_generation: MipArg,
+        /// How many frames to leave out of the statistics at the start
+        #[arg(long, default_value_t = wgpubench::DEFAULT_WARMUP_FRAMES)]
+        warmup_frames: u32,
+        /// Don't render more than this many frames a second
+        #[arg(long, value_parser = parse_rate)]
+        max_fps: Option<f64>,
+        /// Stop after this many frames
+        #[arg(long)]
+        frames: Option<u32>,
+        /// Stop after this many seconds
+        #[arg(long)]
+        duration: Option<f64>,
+    }
+
+    #[derive(Clone, Copy, ValueEnum)]
+    enum LayoutArg {
+        Rings,
+        Grid,
+        Random,
+        Gaussian,
+    }
+
+    #[derive(Clone, Copy, ValueEnum)]
+    enum UploadArg {
+        /// Queue::write_buffer straight into the vertex buffer
+        WriteBuffer,
+        /// Write into a staging buffer, then copy it into the vertex buffer
+        Staged,
+        /// Map a staging buffer for writing, then copy it into the vertex buffer
+        StagingBelt,
+        /// Make a new vertex buffer every frame
+        Recreate,
+    }
+
+    impl From<UploadArg> for UploadStrategy {
+        fn from(value: UploadArg) -> Self {
+            match value {
+                UploadArg::WriteBuffer => UploadStrategy::WriteBuffer,
+                UploadArg::Staged => UploadStrategy::Staged,
+                UploadArg::StagingBelt => UploadStrategy::StagingBelt,
+                UploadArg::Recreate => UploadStrategy::Recreate,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, ValueEnum)]
+    enum PackingArg {
+        /// One texture array layer for each texture
+        Array,
+        /// All the textures side by side in one texture
+        Atlas,
+    }
+
+    impl From<PackingArg> for TexturePacking {
+        fn from(value: PackingArg) -> Self {
+            match value {
+                PackingArg::Array => TexturePacking::Array,
+                PackingArg::Atlas => TexturePacking::Atlas,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, ValueEnum)]
+    enum MipArg {
+        /// Resize the textures on the CPU for every mip level
+        Cpu,
+        /// Draw each mip level from the one above it
+        Gpu,
+    }
+
+    impl From<MipArg> for MipGeneration {
+        fn from(value: MipArg) -> Self {
+            match value {
+                MipArg::Cpu => MipGeneration::Cpu,
+                MipArg::Gpu => MipGeneration::Gpu,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, ValueEnum)]
+    enum BlendArg {
+        Alpha,
+        Additive,
+        Premultiplied,
+        Multiply,
+        Opaque,
+    }
+
+    impl From<BlendArg> for BlendMode {
+        fn from(value: BlendArg) -> Self {
+            match value {
+                BlendArg::Alpha => BlendMode::Alpha,
+                BlendArg::Additive => BlendMode::Additive,
+                BlendArg::Premultiplied => BlendMode::Premultiplied,
+                BlendArg::Multiply => BlendMode::Multiply,
+                BlendArg::Opaque => BlendMode::Opaque,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, ValueEnum)]
+    enum PowerArg {
+        None,
+        Low,
+        High,
+    }
+
+    impl From<PowerArg> for PowerPreference {
+        fn from(value: PowerArg) -> Self {
+            match value {
+                PowerArg::None => PowerPreference::None,
+                PowerArg::Low => PowerPreference::LowPower,
+                PowerArg::High => PowerPreference::HighPerformance,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, ValueEnum)]
+    enum PresentModeArg {
+        Fifo,
+        FifoRelaxed,
+        Immediate,
+        Mailbox,
+        AutoVsync,
+        AutoNoVsync,
+    }
+
+    impl From<PresentModeArg> for PresentMode {
+        fn from(value: PresentModeArg) -> Self {
+            match value {
+                PresentModeArg::Fifo => PresentMode::Fifo,
+                PresentModeArg::FifoRelaxed => PresentMode::FifoRelaxed,
+                PresentModeArg::Immediate => PresentMode::Immediate,
+                PresentModeArg::Mailbox => PresentMode::Mailbox,
+                PresentModeArg::AutoVsync => PresentMode::AutoVsync,
+                PresentModeArg::AutoNoVsync => PresentMode::AutoNoVsync,
+            }
+        }
+    }
+
+    fn parse_backends(list: &str) -> Result<Backends, String> {
+        let list = list.to_lowercase();
+        if list == "all" {
+            return Ok(Backends::all());
+        }
+        let backends = wgpu::util::parse_backends_from_comma_list(&list);
+        if backends.is_empty() {
+            Err(format!("No known backends in {list:?}"))
+        } else {
+            Ok(backends)
+        }
+    }
+
+    fn parse_rate(rate: &str) -> Result<f64, String> {
+        match rate.parse() {
+            Ok(rate) if rate > 0. && f64::is_finite(rate) => Ok(rate),
+            _ => Err(String::from("The rate has to be a number above 0")),
+        }
+    }
+
+    fn parse_sample_count(count: &str) -> Result<u32, String> {
+        match count.parse() {
+            Ok(count @ (1 | 2 | 4 | 8)) => Ok(count),
+            _ => Err(String::from("The sample count has to be 1, 2, 4, or 8")),
+        }
+    }
+
+    impl RunArgs {
+        fn layout(&self) -> Layout {
+            match self.layout {
+                LayoutArg::Rings => Layout::Rings {
+                    rings: self.rings,
+                    per_ring: self.per_ring,
+                    spacing: self.ring_spacing,
+                    hue_turns: self.hue_turns,
+                },
+                LayoutArg::Grid => Layout::Grid,
+                LayoutArg::Random => Layout::UniformRandom,
+                LayoutArg::Gaussian => Layout::GaussianCluster {
+                    clusters: self.clusters,
+                    spread: self.cluster_spread,
+                },
+            }
+        }
+        fn config(self) -> BenchConfig {
+            BenchConfig {
+                width: self.width,
+                height: self.height,
+                backends: self.backend,
+                power_preference: self.power.into(),
+                prefer_fallback_adapter: self.fallback_adapter,
+                present_mode: self.present_mode.into(),
+                srgb: !self.linear_output,
+                sample_count: self.msaa,
+                alpha_to_coverage: !self.no_alpha_to_coverage,
+                blend_modes: self.blend.iter().copied().map(BlendMode::from).collect(),
+                instance_count: self.instances,
+                layout: self.layout(),
+                seed: self.seed,
+                animate: !self.no_animation,
+                update_rate: self.update_rate,
+                upload: self.upload.into(),
+                gpu_simulation: !self.cpu_simulation,
+                textures: self.textures,
+                texture_packing: self.texture_packing.into(),
+                mip_generation: self.mip_generation.into(),
+                warmup_frames: self.warmup_frames,
+                max_frame_rate: self.max_fps,
+                frames: self.frames,
+                duration: self.duration,
+                sweep: None,
+            }
+        }
+    }
+
+    fn run(args: RunArgs, sweep: Option<SweepConfig>) {
+        let headless = args.headless;
+        let config = BenchConfig {
+            sweep,
+            ..args.config()
+        };
+        if headless {
+            if let Err(error) = executor::block_on(wgpubench::run_headless(config)) {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        } else if let Err(error) = executor::block_on(wgpubench::run(config)) {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    }
+
+    pub fn main() {
+        let cli = Cli::parse();
+        match cli.command {
+            Some(Command::ListAdapters { backend }) => {
+                for info in wgpubench::list_adapters(backend) {
+                    println!(
+                        "{} ({:?}, {:?}) driver: {} {}",
+                        info.name, info.backend, info.device_type, info.driver, info.driver_info
+                    );
+                }
+            }
+            Some(Command::Run(args)) => run(args, None),
+            Some(Command::Sweep { sweep, run: args }) => run(args, Some(sweep.into())),
+            None => run(cli.run, None),
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
 fn main() {
-    executor::block_on(wgpubench::run());
+    native::main();
 }
+
+#[cfg(target_family = "wasm")]
+fn main() {}
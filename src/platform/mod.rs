@@ -8,13 +8,48 @@ mod wasm;
 #[cfg(target_family="wasm")]
 use wasm::*;
 
-use std::error::Error;
+use std::{error::Error, future::Future};
 
-// 'static ensures the string is built-in
-pub async fn read_text_asset(filename: &'static str) -> Result<String, Box<dyn Error>> {
+pub async fn read_text_asset(filename: &str) -> Result<String, Box<dyn Error>> {
     read_text_asset_impl(filename).await.map_err(Box::from)
 }
 
-pub async fn read_asset(filename: &'static str) -> Result<Vec<u8>, Box<dyn Error>> {
+pub async fn read_asset(filename: &str) -> Result<Vec<u8>, Box<dyn Error>> {
     read_asset_impl(filename).await.map_err(Box::from)
 }
+
+/// Write benchmark results to a file. On the web, they get printed to the
+/// console instead.
+pub fn write_output(filename: &str, contents: &str) -> Result<(), Box<dyn Error>> {
+    write_output_impl(filename, contents).map_err(Box::from)
+}
+
+/// Write binary output, like a screenshot, to a file. There's no file system
+/// on the web, so this fails there.
+pub fn write_binary_output(filename: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    write_binary_output_impl(filename, contents).map_err(Box::from)
+}
+
+/// Run a task in the background. It should send its result back through an
+/// `EventLoopProxy`. On the web, the browser runs it. Natively, it's run by
+/// `run_tasks`.
+pub fn spawn(task: impl Future<Output = ()> + 'static) {
+    spawn_impl(task)
+}
+
+/// Make progress on the tasks from `spawn`, until they're all waiting on
+/// something. Call this on every turn of the event loop.
+pub fn run_tasks() {
+    run_tasks_impl()
+}
+
+/// Milliseconds since some arbitrary point in time. Only useful for measuring
+/// how long something takes.
+pub fn now() -> f64 {
+    now_impl()
+}
+
+/// Print a message to stdout, or the browser console
+pub fn log(message: &str) {
+    log_impl(message)
+}
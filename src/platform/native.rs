@@ -1,8 +1,19 @@
 use std::{
+    cell::RefCell,
     fs::File,
-    io::{Read, Result},
+    future::Future,
+    io::{Read, Result, Write},
+    sync::OnceLock,
+    time::Instant,
 };
 
+use futures::{executor::LocalPool, task::LocalSpawnExt};
+
+thread_local! {
+    /// Tasks run on the thread that spawned them, which is the event loop's
+    static TASKS: RefCell<LocalPool> = RefCell::new(LocalPool::new());
+}
+
 pub(super) async fn read_text_asset_impl(filename: &str) -> Result<String> {
     let mut file = File::open(filename)?;
     let mut text = String::new();
@@ -16,3 +27,31 @@ pub(super) async fn read_asset_impl(filename: &str) -> Result<Vec<u8>> {
     file.read_to_end(&mut contents)?;
     Ok(contents)
 }
+
+pub(super) fn now_impl() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.
+}
+
+pub(super) fn log_impl(message: &str) {
+    println!("{message}");
+}
+
+pub(super) fn write_output_impl(filename: &str, contents: &str) -> Result<()> {
+    let mut file = File::create(filename)?;
+    file.write_all(contents.as_bytes())
+}
+
+pub(super) fn write_binary_output_impl(filename: &str, contents: &[u8]) -> Result<()> {
+    let mut file = File::create(filename)?;
+    file.write_all(contents)
+}
+
+pub(super) fn spawn_impl(task: impl Future<Output = ()> + 'static) {
+    TASKS.with(|tasks| tasks.borrow().spawner().spawn_local(task))
+        .expect("The task pool has shut down");
+}
+
+pub(super) fn run_tasks_impl() {
+    TASKS.with(|tasks| tasks.borrow_mut().run_until_stalled());
+}
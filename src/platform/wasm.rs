@@ -1,5 +1,6 @@
-use std::error::Error;
+use std::{error::Error, future::Future};
 use gloo::net::http::Request;
+use wasm_bindgen::JsValue;
 
 pub(super) async fn read_text_asset_impl(filename: &str) -> Result<String, Box<dyn Error>> {
     let req_url = String::from("/") + filename;
@@ -12,3 +13,32 @@ pub(super) async fn read_asset_impl(filename: &str) -> Result<Vec<u8>, Box<dyn E
     let resp = Request::get(&req_url).send().await?;
     Ok(resp.binary().await?)
 }
+
+pub(super) fn now_impl() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map(|performance| performance.now())
+        .unwrap_or_default()
+}
+
+pub(super) fn log_impl(message: &str) {
+    web_sys::console::log_1(&JsValue::from_str(message));
+}
+
+pub(super) fn write_output_impl(filename: &str, contents: &str) -> Result<(), Box<dyn Error>> {
+    // There's no file system, so the console will have to do.
+    web_sys::console::log_2(&JsValue::from_str(filename), &JsValue::from_str(contents));
+    Ok(())
+}
+
+pub(super) fn write_binary_output_impl(filename: &str, _contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    Err(Box::from(format!("There's no file system to save {filename} to")))
+}
+
+pub(super) fn spawn_impl(task: impl Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(task);
+}
+
+pub(super) fn run_tasks_impl() {
+    // The browser runs the tasks
+}
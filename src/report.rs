@@ -0,0 +1,170 @@
+use std::{error::Error, fmt::Write};
+
+use serde::Serialize;
+use wgpu::{AdapterInfo, PresentMode, TextureFormat};
+
+use crate::{
+    platform,
+    stats::{FrameSample, FrameStats, Summary, SurfaceEvents},
+    scene::Scene,
+};
+
+pub const JSON_OUTPUT: &str = "bench_output.json";
+pub const CSV_OUTPUT: &str = "bench_output.csv";
+pub const TEXT_OUTPUT: &str = "bench_output.txt";
+
+/// What was benchmarked, and on what
+#[derive(Debug, Clone, Serialize)]
+pub struct RunInfo {
+    pub adapter: String,
+    pub backend: String,
+    pub driver: String,
+    pub driver_info: String,
+    pub surface_format: String,
+    pub sample_count: u32,
+    /// `None` when rendering offscreen
+    pub present_mode: Option<String>,
+    pub instance_count: u32,
+    /// The blend mode of each layer, separated by "+"
+    pub blend: String,
+    pub texture_packing: String,
+    /// The format of the flare textures, which is only block-compressed if
+    /// the device supports it
+    pub texture_format: String,
+    pub upload_strategy: String,
+    /// Where the flares were moved: "cpu", "gpu", or "none"
+    pub simulation: String,
+    pub resolution: [u32; 2],
+}
+
+impl RunInfo {
+    pub fn new(
+        adapter: &AdapterInfo,
+        surface_format: TextureFormat,
+        sample_count: u32,
+        present_mode: Option<PresentMode>,
+        scene: &Scene,
+        (width, height): (u32, u32),
+    ) -> Self {
+        Self {
+            adapter: adapter.name.clone(),
+            backend: format!("{:?}", adapter.backend),
+            driver: adapter.driver.clone(),
+            driver_info: adapter.driver_info.clone(),
+            surface_format: format!("{surface_format:?}"),
+            sample_count,
+            present_mode: present_mode.map(|mode| format!("{mode:?}")),
+            instance_count: scene.instance_count(),
+            texture_packing: scene.texture_packing().to_string(),
+            texture_format: format!("{:?}", scene.texture_format()),
+            blend: scene.blend_modes().iter().map(ToString::to_string).collect::<Vec<_>>().join("+"),
+            upload_strategy: scene.upload_strategy().to_string(),
+            simulation: scene.simulation_name().to_string(),
+            resolution: [width, height],
+        }
+    }
+}
+
+/// The results of a benchmark run, in a form that other programs can read
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    #[serde(flatten)]
+    pub info: RunInfo,
+    pub warmup_frames: u32,
+    pub cpu_frame_time: Option<Summary>,
+    pub gpu_pass_time: Option<Summary>,
+    pub upload_time: Option<Summary>,
+    /// Always empty when rendering offscreen
+    pub surface_events: SurfaceEvents,
+    pub frames: Vec<FrameSample>,
+}
+
+/// Quote a CSV field if it needs quoting. Adapter names often have commas in
+/// them.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl BenchReport {
+    pub fn new(info: RunInfo, stats: &FrameStats) -> Self {
+        Self {
+            info,
+            warmup_frames: stats.warmup_frames(),
+            cpu_frame_time: stats.report(),
+            gpu_pass_time: stats.gpu_report(),
+            upload_time: stats.upload_report(),
+            surface_events: stats.surface_events(),
+            frames: stats.samples().collect(),
+        }
+    }
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+    /// One row per frame. The run info is repeated on every row, so that rows
+    /// from different runs can be thrown into the same table.
+    pub fn to_csv(&self) -> String {
+        let info = &self.info;
+        let run_columns = [
+            csv_field(&info.adapter),
+            csv_field(&info.backend),
+            csv_field(&info.driver),
+            csv_field(&info.driver_info),
+            csv_field(&info.surface_format),
+            info.sample_count.to_string(),
+            csv_field(info.present_mode.as_deref().unwrap_or("")),
+            info.instance_count.to_string(),
+            csv_field(&info.blend),
+            csv_field(&info.texture_packing),
+            csv_field(&info.texture_format),
+            csv_field(&info.upload_strategy),
+            csv_field(&info.simulation),
+            info.resolution[0].to_string(),
+            info.resolution[1].to_string(),
+        ].join(",");
+        let mut csv = String::from(
+            "adapter,backend,driver,driver_info,surface_format,sample_count,present_mode,instance_count,blend,texture_packing,texture_format,upload_strategy,simulation,width,height,frame,cpu_frame_time_ms,gpu_pass_time_ms,upload_time_ms\n"
+        );
+        self.frames.iter().for_each(|sample| {
+            let gpu_pass_time = sample.gpu_pass_time.map(|time| time.to_string()).unwrap_or_default();
+            let upload_time = sample.upload_time.map(|time| time.to_string()).unwrap_or_default();
+            // Writing to a String can't fail
+            let _ = writeln!(csv, "{run_columns},{},{},{gpu_pass_time},{upload_time}", sample.frame, sample.cpu_frame_time);
+        });
+        csv
+    }
+    /// A human-readable summary of the run
+    pub fn to_text(&self) -> String {
+        let info = &self.info;
+        let mut text = format!(
+            "{} ({}, {} {})\n{} {}x{} {}x MSAA, {} instances, blend {}, {} {} textures, present mode {}, upload strategy {}, simulation {}\n",
+            info.adapter, info.backend, info.driver, info.driver_info,
+            info.surface_format, info.resolution[0], info.resolution[1], info.sample_count,
+            info.instance_count, info.blend, info.texture_packing, info.texture_format, info.present_mode.as_deref().unwrap_or("none"),
+            info.upload_strategy, info.simulation,
+        );
+        if let Some(summary) = &self.cpu_frame_time {
+            let _ = write!(text, "CPU frame time: {summary}");
+        }
+        if let Some(summary) = &self.gpu_pass_time {
+            let _ = write!(text, "GPU render pass time: {summary}");
+        }
+        if let Some(summary) = &self.upload_time {
+            let _ = write!(text, "Instance upload time: {summary}");
+        }
+        if !self.surface_events.is_empty() {
+            let _ = writeln!(text, "Surface events: {}", self.surface_events);
+        }
+        text
+    }
+    /// Write the results out as JSON, CSV, and text
+    pub fn write(&self) -> Result<(), Box<dyn Error>> {
+        platform::write_output(JSON_OUTPUT, &self.to_json()?)?;
+        platform::write_output(CSV_OUTPUT, &self.to_csv())?;
+        platform::write_output(TEXT_OUTPUT, &self.to_text())?;
+        Ok(())
+    }
+}
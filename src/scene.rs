@@ -0,0 +1,340 @@
+use std::{error::Error, mem};
+
+use wgpu::*;
+
+use crate::{
+    config::BenchConfig,
+    flare_textures::{FlareTextures, TexturePacking},
+    gpu_simulation::{self, GpuSimulation},
+    layout::Layout,
+    platform,
+    simulation::{self, FixedTimestep},
+    square::{BlendMode, SquarePipeline, SquareUniforms, SquareInstance, SquareInstanceRaw},
+    upload::{InstanceBuffer, UploadStrategy},
+    util::{context::GpuContext, texture::RenderTarget},
+};
+
+/// The benchmark workload: a bunch of textured flares drawn with the square
+/// pipeline. Shared by the windowed app and the headless runner, so both of
+/// them render exactly the same thing.
+pub struct Scene {
+    square_pipeline: SquarePipeline,
+    square_uniforms: SquareUniforms,
+    square_instances: Vec<SquareInstance>,
+    /// Reused every frame, so that uploading the instances doesn't allocate
+    square_instance_data: Vec<SquareInstanceRaw>,
+    square_instance_count: u32,
+    simulation: Simulation,
+    layout: Layout,
+    seed: u64,
+    /// How many flare textures there are
+    texture_count: u32,
+    texture_packing: TexturePacking,
+    texture_format: TextureFormat,
+    /// One blend mode for each layer. The instances are split evenly between
+    /// the layers, and the layers are drawn in order.
+    blend_modes: Vec<BlendMode>,
+    animate: bool,
+    upload_strategy: UploadStrategy,
+    /// How often the instances are moved
+    timestep: FixedTimestep,
+    /// Whether the instances are held still. Frames are still rendered.
+    paused: bool,
+    /// Steps to take while paused, one for each time `step_once` is called
+    queued_steps: u32,
+    /// Whether the instances have moved since they were last uploaded
+    needs_upload: bool,
+}
+
+/// How far the view can be zoomed out and in
+const MIN_ZOOM: f32 = 0.125;
+const MAX_ZOOM: f32 = 64.;
+
+/// Where the flares get moved, and so where the instance buffer comes from
+enum Simulation {
+    /// Moved on the CPU, and uploaded every frame
+    Cpu(InstanceBuffer),
+    /// Moved by a compute shader
+    Gpu {
+        simulation: GpuSimulation,
+        /// How many steps to move the flares the next time the compute shader
+        /// runs
+        pending_steps: u32,
+    },
+}
+
+/// Lay the flares out, and go through the flare textures in turn, so that
+/// each texture is used about as often as the others
+fn generate_instances(layout: Layout, count: u32, seed: u64, texture_count: u32) -> Vec<SquareInstance> {
+    let mut instances = layout.generate(count, seed);
+    instances.iter_mut().for_each(|instance| instance.texture = instance.index % texture_count.max(1));
+    instances
+}
+
+fn instance_data(instances: &[SquareInstance]) -> Vec<SquareInstanceRaw> {
+    instances.iter().copied().map(SquareInstanceRaw::from).collect()
+}
+
+impl Scene {
+    pub async fn new(
+        context: &GpuContext,
+        format: TextureFormat,
+        sample_count: u32,
+        (width, height): (u32, u32),
+        config: &BenchConfig,
+    ) -> Result<Scene, Box<dyn Error>> {
+        let (device, queue) = (&context.device, &context.queue);
+        let blend_modes = if config.blend_modes.is_empty() {
+            vec![BlendMode::default()]
+        } else {
+            config.blend_modes.clone()
+        };
+        let premultiplied = blend_modes.iter().any(|mode| mode.premultiplied());
+        let flare_textures = FlareTextures::load(
+            context, &config.textures, config.texture_packing, premultiplied, config.mip_generation).await?;
+        let texture_count = flare_textures.region_count();
+        let texture_format = flare_textures.texture.format();
+        let square_pipeline = SquarePipeline::new(
+            device, &flare_textures, format, sample_count, config.alpha_to_coverage, &blend_modes).await?;
+        let square_uniforms = SquareUniforms::new([width, height]);
+        queue.write_buffer(&square_pipeline.uniform_buffer, 0, bytemuck::cast_slice(&[square_uniforms]));
+        let square_instances = generate_instances(config.layout, config.instance_count, config.seed, texture_count);
+        let square_instance_count = square_instances.len() as u32;
+        let square_instance_data = instance_data(&square_instances);
+        let gpu_supported = gpu_simulation::supported(device);
+        if config.animate && config.gpu_simulation && !gpu_supported {
+            platform::log("Compute shaders are not supported, so the flares will be moved on the CPU");
+        }
+        let simulation = if config.animate && config.gpu_simulation && gpu_supported {
+            Simulation::Gpu {
+                simulation: GpuSimulation::new(device, &square_instances).await?,
+                pending_steps: 0,
+            }
+        } else {
+            Simulation::Cpu(InstanceBuffer::new(
+                device, config.upload, bytemuck::cast_slice(&square_instance_data)))
+        };
+        Ok(Scene {
+            square_pipeline,
+            square_uniforms,
+            square_instances,
+            square_instance_data,
+            square_instance_count,
+            simulation,
+            layout: config.layout,
+            seed: config.seed,
+            texture_count,
+            texture_packing: config.texture_packing,
+            texture_format,
+            blend_modes,
+            animate: config.animate,
+            upload_strategy: config.upload,
+            timestep: FixedTimestep::new(config.update_rate),
+            paused: false,
+            queued_steps: 0,
+            needs_upload: false,
+        })
+    }
+    pub fn resize(&mut self, queue: &Queue, (width, height): (u32, u32)) {
+        self.square_uniforms.screen_size = [width, height];
+        self.write_uniforms(queue);
+    }
+    fn write_uniforms(&self, queue: &Queue) {
+        queue.write_buffer(&self.square_pipeline.uniform_buffer, 0, bytemuck::cast_slice(&[self.square_uniforms]))
+    }
+    /// Move the view by `delta`, in clip space
+    pub fn pan(&mut self, queue: &Queue, delta: [f32; 2]) {
+        let offset = &mut self.square_uniforms.offset;
+        offset[0] += delta[0];
+        offset[1] += delta[1];
+        self.write_uniforms(queue);
+    }
+    /// Zoom in by `factor`, or out if it's less than 1, keeping the point at
+    /// `around` (in clip space) where it is on screen
+    pub fn zoom(&mut self, queue: &Queue, factor: f32, around: [f32; 2]) {
+        let uniforms = &mut self.square_uniforms;
+        let zoom = (uniforms.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let factor = zoom / uniforms.zoom;
+        uniforms.offset = [0, 1].map(|axis| around[axis] - (around[axis] - uniforms.offset[axis]) * factor);
+        uniforms.zoom = zoom;
+        self.write_uniforms(queue);
+    }
+    /// Go back to the whole scene, unzoomed
+    pub fn reset_view(&mut self, queue: &Queue) {
+        self.square_uniforms = SquareUniforms::new(self.square_uniforms.screen_size);
+        self.write_uniforms(queue);
+    }
+    pub fn instance_count(&self) -> u32 {
+        self.square_instance_count
+    }
+    /// The most instances the buffers can hold on this device. Storage
+    /// buffers are smaller than vertex buffers, so fewer fit when the flares
+    /// are moved on the GPU.
+    pub fn max_instance_count(&self, device: &Device) -> u32 {
+        match self.simulation {
+            Simulation::Cpu(_) => {
+                let per_instance = mem::size_of::<SquareInstanceRaw>() as u64;
+                (device.limits().max_buffer_size / per_instance).min(u32::MAX as u64) as u32
+            }
+            Simulation::Gpu { .. } => gpu_simulation::max_instance_count(device),
+        }
+    }
+    /// Regenerate the layout with a different number of instances, and
+    /// rebuild the instance buffer to match.
+    pub fn set_instance_count(&mut self, device: &Device, count: u32) {
+        self.square_instances = generate_instances(self.layout, count, self.seed, self.texture_count);
+        self.square_instance_count = count;
+        self.square_instance_data = instance_data(&self.square_instances);
+        match &mut self.simulation {
+            Simulation::Cpu(buffer) => *buffer = InstanceBuffer::new(
+                device, self.upload_strategy, bytemuck::cast_slice(&self.square_instance_data)),
+            Simulation::Gpu { simulation, .. } => simulation.set_instances(device, &self.square_instances),
+        }
+        self.needs_upload = false;
+    }
+    /// Put the flares back where they started, keeping the instance count
+    pub fn reset_layout(&mut self, device: &Device) {
+        self.set_instance_count(device, self.square_instance_count);
+    }
+    /// Pick up where another scene left off, with the same instance count and
+    /// view, paused if it was paused. The flares start from the beginning of
+    /// the layout.
+    pub fn carry_over(&mut self, device: &Device, queue: &Queue, old: &Scene) {
+        self.set_instance_count(device, old.square_instance_count);
+        self.square_uniforms = old.square_uniforms;
+        self.write_uniforms(queue);
+        self.paused = old.paused;
+    }
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.queued_steps = 0;
+    }
+    /// Move the flares one step on the next update, if they're paused
+    pub fn step_once(&mut self) {
+        if self.paused {
+            self.queued_steps += 1;
+        }
+    }
+    pub fn texture_packing(&self) -> TexturePacking {
+        self.texture_packing
+    }
+    pub fn texture_format(&self) -> TextureFormat {
+        self.texture_format
+    }
+    pub fn blend_modes(&self) -> &[BlendMode] {
+        &self.blend_modes
+    }
+    pub fn upload_strategy(&self) -> UploadStrategy {
+        self.upload_strategy
+    }
+    /// Where the flares are being moved: "cpu", "gpu", or "none" if they
+    /// aren't moving
+    pub fn simulation_name(&self) -> &'static str {
+        match self.simulation {
+            _ if !self.animate => "none",
+            Simulation::Cpu(_) => "cpu",
+            Simulation::Gpu { .. } => "gpu",
+        }
+    }
+    /// Move the flares along, in as many fixed-size steps as fit in the time
+    /// since the last update, or only the queued steps if they're paused.
+    /// `now` is in milliseconds. On the GPU, this only takes note of the
+    /// steps, and the flares are moved by `simulate`.
+    pub fn update(&mut self, now: f64) {
+        let mut steps = self.timestep.advance(now);
+        if self.paused {
+            steps = std::mem::take(&mut self.queued_steps);
+        }
+        if !self.animate || steps == 0 {
+            return;
+        }
+        if let Simulation::Gpu { pending_steps, .. } = &mut self.simulation {
+            *pending_steps += steps;
+            return;
+        }
+        let dt = self.timestep.step_size();
+        (0..steps).for_each(|_| simulation::step(&mut self.square_instances, dt));
+        self.square_instance_data.iter_mut()
+            .zip(self.square_instances.iter().copied())
+            .for_each(|(data, instance)| *data = instance.into());
+        self.needs_upload = true;
+    }
+    /// Upload the instances if they have moved, recording any copies into
+    /// `commands`. Returns how long the upload took on the CPU, in
+    /// milliseconds, or `None` if there was nothing to upload.
+    pub fn upload(&mut self, device: &Device, queue: &Queue, commands: &mut CommandEncoder) -> Option<f64> {
+        let Simulation::Cpu(buffer) = &mut self.simulation else {
+            return None;
+        };
+        if !std::mem::take(&mut self.needs_upload) {
+            return None;
+        }
+        let start = platform::now();
+        buffer.upload(device, queue, commands, bytemuck::cast_slice(&self.square_instance_data));
+        Some(platform::now() - start)
+    }
+    /// Record a compute pass for each step the flares need to be moved, if
+    /// they're being moved on the GPU.
+    pub fn simulate(&mut self, device: &Device, queue: &Queue, commands: &mut CommandEncoder) {
+        if let Simulation::Gpu { simulation, pending_steps } = &mut self.simulation {
+            let dt = self.timestep.step_size();
+            (0..std::mem::take(pending_steps)).for_each(|_| simulation.step(device, queue, commands, dt));
+        }
+    }
+    /// Call this once the commands passed to `upload` have been submitted
+    pub fn after_submit(&mut self) {
+        if let Simulation::Cpu(buffer) = &mut self.simulation {
+            buffer.after_submit();
+        }
+    }
+    /// Record the render pass that draws the scene into the given target
+    pub fn encode(&self, commands: &mut CommandEncoder, target: &RenderTarget) {
+        let mut render_pass = commands.begin_render_pass(&RenderPassDescriptor {
+            label: Some("My render pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target.color,
+                resolve_target: target.resolve,
+                ops: Operations {
+                    load: LoadOp::Clear(Color {
+                        r: 0.125,
+                        g: 0.125,
+                        b: 0.25,
+                        a: 1.0,
+                    }),
+                    // Only the resolved image is needed
+                    store: target.resolve.is_none(),
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: target.depth,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        let instance_buffer = match &self.simulation {
+            Simulation::Cpu(buffer) => buffer.buffer(),
+            Simulation::Gpu { simulation, .. } => simulation.instance_buffer(),
+        };
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.square_pipeline.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.square_pipeline.index_buffer.slice(..), IndexFormat::Uint16);
+        let layers = self.blend_modes.len() as u32;
+        self.blend_modes.iter().enumerate().for_each(|(layer, &blend_mode)| {
+            let layer = layer as u32;
+            let first = (self.square_instance_count as u64 * layer as u64 / layers as u64) as u32;
+            let end = (self.square_instance_count as u64 * (layer + 1) as u64 / layers as u64) as u32;
+            render_pass.set_pipeline(self.square_pipeline.pipeline(blend_mode));
+            render_pass.set_bind_group(0, self.square_pipeline.bind_group(blend_mode), &[]);
+            render_pass.draw_indexed(
+                0..crate::square::SQUARE_INDX.len() as u32,
+                0,
+                first..end);
+        });
+    }
+}
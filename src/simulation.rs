@@ -0,0 +1,68 @@
+use std::f32::consts::TAU;
+
+use crate::square::SquareInstance;
+
+/// How fast the hue of each flare goes around the colour wheel, in radians
+/// per second
+pub const HUE_SPEED: f32 = 0.5;
+/// The longest time step, in seconds. A long stall (e.g. the window being
+/// dragged around) shouldn't throw the flares off the screen.
+pub const MAX_TIME_STEP: f32 = 0.1;
+/// How many times a second the flares are moved, by default
+pub const DEFAULT_UPDATE_RATE: f64 = 60.;
+
+/// Splits the time between frames into steps of the same size, and carries
+/// what's left over on to the next frame, so that the flares move the same
+/// way however fast the frames are rendered.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedTimestep {
+    /// In seconds
+    step: f64,
+    /// Time that hasn't been stepped through yet, in seconds
+    accumulated: f64,
+    /// In milliseconds
+    last_update: Option<f64>,
+}
+
+impl FixedTimestep {
+    /// `rate` is how many steps to take every second
+    pub fn new(rate: f64) -> Self {
+        Self {
+            step: 1. / rate.max(f64::MIN_POSITIVE),
+            accumulated: 0.,
+            last_update: None,
+        }
+    }
+    /// How long each step is, in seconds
+    pub fn step_size(&self) -> f32 {
+        self.step as f32
+    }
+    /// How many steps to take, given the current time in milliseconds. After
+    /// a long stall, only `MAX_TIME_STEP` seconds are caught up on.
+    pub fn advance(&mut self, now: f64) -> u32 {
+        let elapsed = self.last_update.replace(now).map_or(0., |last_update| (now - last_update) / 1000.);
+        self.accumulated = (self.accumulated + elapsed.max(0.)).min((MAX_TIME_STEP as f64).max(self.step));
+        let steps = (self.accumulated / self.step).floor();
+        self.accumulated -= steps * self.step;
+        steps as u32
+    }
+}
+
+/// Move every flare along its velocity, bouncing off the edges of the screen,
+/// and shift its hue. `dt` is in seconds.
+pub fn step(instances: &mut [SquareInstance], dt: f32) {
+    let dt = dt.clamp(0., MAX_TIME_STEP);
+    instances.iter_mut().for_each(|instance| {
+        instance.pos += instance.vel * dt;
+        // Reflect off the edges of clip space
+        if instance.pos.x.abs() > 1. {
+            instance.vel.x = -instance.vel.x;
+            instance.pos.x = instance.pos.x.clamp(-1., 1.);
+        }
+        if instance.pos.y.abs() > 1. {
+            instance.vel.y = -instance.vel.y;
+            instance.pos.y = instance.pos.y.clamp(-1., 1.);
+        }
+        instance.hue = (instance.hue + HUE_SPEED * dt).rem_euclid(TAU);
+    });
+}
@@ -1,6 +1,6 @@
 use bytemuck::{Pod, Zeroable};
 use glam::Vec2;
-use std::{borrow::Cow, error::Error, mem, ops::Deref};
+use std::{borrow::Cow, error::Error, fmt, mem};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
@@ -8,11 +8,14 @@ use wgpu::{
     FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState, RenderPipeline,
     RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderStages,
     TextureFormat, VertexBufferLayout, VertexState, VertexStepMode, BlendState, ColorWrites, VertexAttribute,
+    BlendComponent, BlendFactor, BlendOperation,
 };
 
-use crate::{platform, util::texture::Texture};
-
-pub const SQUARE_SIZE: f32 = 8.0; // pixels
+use crate::{
+    flare_textures::FlareTextures,
+    platform,
+    util::texture::{Texture, DEPTH_FORMAT},
+};
 
 const SQUARE_GEOM: [SquareVertexRaw; 4] = [
     SquareVertexRaw::const_from(SquareVertex {
@@ -67,15 +70,19 @@ impl From<SquareVertex> for SquareVertexRaw {
 
 impl VertexAttributes for SquareVertexRaw {
     fn vertex_attributes(start_index: u32) -> Box<[VertexAttribute]> {
-        Box::from(wgpu::vertex_attr_array![0 + start_index => Float32x4])
+        Box::from(wgpu::vertex_attr_array![start_index => Float32x4])
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SquareInstance {
     pub pos: Vec2,
+    /// Clip space units per second
+    pub vel: Vec2,
     pub hue: f32,
     pub index: u32,
+    /// Which flare texture to draw with, as an index into the region table
+    pub texture: u32,
 }
 
 trait VertexAttributes {
@@ -86,19 +93,21 @@ trait VertexAttributes {
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct SquareInstanceRaw {
     pos_hue: [f32; 4],
+    texture: u32,
 }
 
 impl From<SquareInstance> for SquareInstanceRaw {
     fn from(value: SquareInstance) -> Self {
         Self {
             pos_hue: [value.pos.x, value.pos.y, value.hue, f32::from_bits(value.index)],
+            texture: value.texture,
         }
     }
 }
 
 impl VertexAttributes for SquareInstanceRaw {
     fn vertex_attributes(start_index: u32) -> Box<[VertexAttribute]> {
-        Box::from(wgpu::vertex_attr_array![0 + start_index => Float32x4])
+        Box::from(wgpu::vertex_attr_array![start_index => Float32x4, start_index + 1 => Uint32])
     }
 }
 
@@ -106,18 +115,123 @@ impl VertexAttributes for SquareInstanceRaw {
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct SquareUniforms {
     pub screen_size: [u32; 2],
+    /// Where the middle of the scene is on screen, in clip space
+    pub offset: [f32; 2],
+    /// How much bigger than normal the scene is drawn
+    pub zoom: f32,
+    pub _padding: f32,
+}
+
+impl SquareUniforms {
+    pub fn new(screen_size: [u32; 2]) -> Self {
+        Self {
+            screen_size,
+            offset: [0., 0.],
+            zoom: 1.,
+            _padding: 0.,
+        }
+    }
+}
+
+/// How flares are blended with what's behind them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Straight alpha blending
+    #[default]
+    Alpha,
+    /// Added on top, which is how lens flares are usually drawn
+    Additive,
+    /// Alpha blending with a premultiplied texture
+    Premultiplied,
+    /// Darkens what's behind, using a premultiplied texture
+    Multiply,
+    /// No blending at all
+    Opaque,
+}
+
+impl BlendMode {
+    fn blend_state(self) -> Option<BlendState> {
+        match self {
+            BlendMode::Alpha => Some(BlendState::ALPHA_BLENDING),
+            BlendMode::Additive => Some(BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+            }),
+            BlendMode::Premultiplied => Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            // dst * (src + 1 - src alpha), which is dst * mix(1, colour, alpha)
+            // when src is premultiplied
+            BlendMode::Multiply => Some(BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::Dst,
+                    dst_factor: BlendFactor::OneMinusSrcAlpha,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent::OVER,
+            }),
+            BlendMode::Opaque => None,
+        }
+    }
+    /// Whether this mode uses a premultiplied texture, and outputs
+    /// premultiplied colours
+    pub fn premultiplied(self) -> bool {
+        matches!(self, BlendMode::Premultiplied | BlendMode::Multiply)
+    }
+    /// The mode after this one, for switching through them
+    pub fn next(self) -> Self {
+        match self {
+            BlendMode::Alpha => BlendMode::Additive,
+            BlendMode::Additive => BlendMode::Premultiplied,
+            BlendMode::Premultiplied => BlendMode::Multiply,
+            BlendMode::Multiply => BlendMode::Opaque,
+            BlendMode::Opaque => BlendMode::Alpha,
+        }
+    }
+}
+
+impl fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BlendMode::Alpha => "alpha",
+            BlendMode::Additive => "additive",
+            BlendMode::Premultiplied => "premultiplied",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Opaque => "opaque",
+        })
+    }
 }
 
+/// The square pipeline, with a variant for each blend mode in use
 pub struct SquarePipeline {
-    pub pipeline: RenderPipeline,
+    pipelines: Vec<(BlendMode, RenderPipeline)>,
     pub uniform_buffer: wgpu::Buffer,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
-    pub bind_group: wgpu::BindGroup,
+    bind_group: wgpu::BindGroup,
+    /// Only there if a blend mode needs a premultiplied texture
+    premultiplied_bind_group: Option<wgpu::BindGroup>,
 }
 
 impl SquarePipeline {
-    pub async fn new(device: &Device, texture: &Texture, surffmt: TextureFormat) -> Result<Self, Box<dyn Error>> {
+    /// Build a variant of the pipeline for each of `blend_modes`. The
+    /// textures have to include premultiplied ones if any of them are
+    /// premultiplied. Alpha-to-coverage is only turned on if `sample_count`
+    /// is more than 1, since there's no coverage to speak of otherwise.
+    pub async fn new(
+        device: &Device,
+        textures: &FlareTextures,
+        surffmt: TextureFormat,
+        sample_count: u32,
+        alpha_to_coverage: bool,
+        blend_modes: &[BlendMode],
+    ) -> Result<Self, Box<dyn Error>> {
         let shader_code = Cow::from(platform::read_text_asset("assets/square.wgsl").await?);
         let shader_module = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Square shader module"),
@@ -141,7 +255,7 @@ impl SquarePipeline {
                     visibility: ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
                         multisampled: false,
                     },
                     count: None,
@@ -152,6 +266,16 @@ impl SquarePipeline {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
         let uniform_buffer = device.create_buffer(&BufferDescriptor {
@@ -170,7 +294,12 @@ impl SquarePipeline {
             contents: bytemuck::cast_slice(&SQUARE_INDX),
             usage: BufferUsages::INDEX,
         });
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        let region_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Flare texture regions"),
+            contents: bytemuck::cast_slice(&textures.region_data()),
+            usage: BufferUsages::UNIFORM,
+        });
+        let create_bind_group = |texture: &Texture| device.create_bind_group(&BindGroupDescriptor {
             label: Some("Square uniforms"),
             layout: &bind_group_layout,
             entries: &[
@@ -186,14 +315,23 @@ impl SquarePipeline {
                     binding: 2,
                     resource: wgpu::BindingResource::Sampler(&texture.sampler),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: region_buffer.as_entire_binding(),
+                },
             ],
         });
+        let bind_group = create_bind_group(&textures.texture);
+        let premultiplied_bind_group = textures.premultiplied.as_ref().map(create_bind_group);
+        if blend_modes.iter().any(|mode| mode.premultiplied()) && premultiplied_bind_group.is_none() {
+            return Err(Box::from("A premultiplied blend mode was asked for, without a premultiplied texture"));
+        }
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Pipeline for rendering a textured square (layout)"),
             bind_group_layouts: &[&bind_group_layout],
             push_constant_ranges: &[],
         });
-        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        let create_pipeline = |blend_mode: BlendMode| device.create_render_pipeline(&RenderPipelineDescriptor {
             label: Some("Pipeline for rendering a textured square"),
             layout: Some(&pipeline_layout),
             vertex: VertexState {
@@ -208,7 +346,7 @@ impl SquarePipeline {
                     VertexBufferLayout {
                         array_stride: mem::size_of::<SquareVertexRaw>() as BufferAddress,
                         step_mode: VertexStepMode::Vertex,
-                        attributes: &SquareVertexRaw::vertex_attributes(1),
+                        attributes: &SquareVertexRaw::vertex_attributes(2),
                     },
                 ],
             },
@@ -222,42 +360,56 @@ impl SquarePipeline {
                 conservative: false,
             },
             depth_stencil: Some(DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
+                format: DEPTH_FORMAT,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Always,
                 stencil: Default::default(),
                 bias: Default::default(),
             }),
             multisample: MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
-                alpha_to_coverage_enabled: false,
+                alpha_to_coverage_enabled: alpha_to_coverage && sample_count > 1,
             },
             fragment: Some(FragmentState {
                 module: &shader_module,
-                entry_point: "pixel_main",
+                entry_point: if blend_mode.premultiplied() { "pixel_main_premultiplied" } else { "pixel_main" },
                 targets: &[Some(ColorTargetState {
                     format: surffmt,
-                    blend: Some(BlendState::ALPHA_BLENDING),
+                    blend: blend_mode.blend_state(),
                     write_mask: ColorWrites::ALL,
                 })],
             }),
             multiview: None,
         });
+        let mut pipelines: Vec<(BlendMode, RenderPipeline)> = Vec::new();
+        for &blend_mode in blend_modes {
+            if !pipelines.iter().any(|&(mode, _)| mode == blend_mode) {
+                pipelines.push((blend_mode, create_pipeline(blend_mode)));
+            }
+        }
         Ok(SquarePipeline {
-            pipeline,
+            pipelines,
             bind_group,
+            premultiplied_bind_group,
             uniform_buffer,
             vertex_buffer,
             index_buffer,
         })
     }
-}
-
-impl Deref for SquarePipeline {
-    type Target = RenderPipeline;
-
-    fn deref(&self) -> &Self::Target {
-        &self.pipeline
+    /// The variant of the pipeline for the given blend mode, which has to be
+    /// one of the modes the pipeline was made with
+    pub fn pipeline(&self, blend_mode: BlendMode) -> &RenderPipeline {
+        self.pipelines.iter()
+            .find(|&&(mode, _)| mode == blend_mode)
+            .map(|(_, pipeline)| pipeline)
+            .expect("No pipeline for this blend mode")
+    }
+    /// The bind group with the right texture for the given blend mode
+    pub fn bind_group(&self, blend_mode: BlendMode) -> &wgpu::BindGroup {
+        match &self.premultiplied_bind_group {
+            Some(bind_group) if blend_mode.premultiplied() => bind_group,
+            _ => &self.bind_group,
+        }
     }
 }
@@ -0,0 +1,326 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::platform;
+
+/// How many frames to throw away before recording, so that shader
+/// compilation, resource uploads, and the driver warming up don't skew the
+/// results.
+pub const DEFAULT_WARMUP_FRAMES: u32 = 60;
+/// How many buckets the histogram of frame times has
+pub const HISTOGRAM_BUCKETS: usize = 16;
+
+/// Collects CPU frame times (the time between the start of one frame and the
+/// start of the next), instance upload times, and GPU render pass times if
+/// they're available, skipping the first few frames.
+pub struct FrameStats {
+    warmup_frames: u32,
+    frames_seen: u32,
+    last_frame_start: Option<f64>,
+    /// Indexed by frame, starting after the warmup. Frames that ended with a
+    /// skipped frame don't have a time.
+    cpu_frame_times: Vec<Option<f64>>,
+    /// GPU times arrive a few frames late, and some frames don't get timed at
+    /// all, so this is indexed by frame, like `cpu_frame_times`.
+    gpu_pass_times: Vec<Option<f64>>,
+    /// Frames where the instances didn't move don't upload anything
+    upload_times: Vec<Option<f64>>,
+    /// Counted over the whole run, even when the frame stats start over
+    surface_events: SurfaceEvents,
+}
+
+/// How many times each thing went wrong with the window surface or the
+/// device. Frames that were skipped aren't counted as frames.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SurfaceEvents {
+    /// The surface was lost, and had to be configured again
+    pub lost: u32,
+    /// The surface no longer matched the window, and had to be configured
+    /// again
+    pub outdated: u32,
+    /// Getting the next surface texture took too long
+    pub timeouts: u32,
+    /// The run was stopped because there wasn't enough memory for the next
+    /// surface texture
+    pub out_of_memory: u32,
+    /// Frames skipped because the window had no area, like when it's
+    /// minimized
+    pub zero_size_frames: u32,
+    /// Frames skipped because the window was hidden
+    pub occluded_frames: u32,
+    /// The device was lost, and everything on it was made again
+    pub device_lost: u32,
+}
+
+impl SurfaceEvents {
+    pub fn is_empty(&self) -> bool {
+        self.lost == 0 && self.outdated == 0 && self.timeouts == 0 && self.out_of_memory == 0 &&
+        self.zero_size_frames == 0 && self.occluded_frames == 0 && self.device_lost == 0
+    }
+}
+
+impl fmt::Display for SurfaceEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "lost {}, outdated {}, timeouts {}, out of memory {}, zero-size frames {}, occluded frames {}, device lost {}",
+            self.lost, self.outdated, self.timeouts, self.out_of_memory,
+            self.zero_size_frames, self.occluded_frames, self.device_lost,
+        )
+    }
+}
+
+/// Put a time into a list indexed by frame, skipping warmup frames
+fn record(times: &mut Vec<Option<f64>>, warmup_frames: u32, frame: u32, time: f64) {
+    let Some(index) = frame.checked_sub(warmup_frames) else {
+        return;
+    };
+    let index = index as usize;
+    if times.len() <= index {
+        times.resize(index + 1, None);
+    }
+    times[index] = Some(time);
+}
+
+impl FrameStats {
+    pub fn new(warmup_frames: u32) -> Self {
+        Self {
+            warmup_frames,
+            frames_seen: 0,
+            last_frame_start: None,
+            cpu_frame_times: Vec::new(),
+            gpu_pass_times: Vec::new(),
+            upload_times: Vec::new(),
+            surface_events: SurfaceEvents::default(),
+        }
+    }
+    /// Call this at the start of every frame, with the current time in
+    /// milliseconds.
+    pub fn begin_frame(&mut self, now: f64) {
+        if let Some(last_frame_start) = self.last_frame_start.replace(now) {
+            // The frame that just ended is frames_seen - 1
+            let frame = self.frames_seen - 1;
+            record(&mut self.cpu_frame_times, self.warmup_frames, frame, now - last_frame_start);
+        }
+        self.frames_seen += 1;
+    }
+    /// Call this instead of `begin_frame` when a frame isn't rendered, so
+    /// that the time until the next frame that is rendered isn't counted as
+    /// a frame time.
+    pub fn skip_frame(&mut self) {
+        self.last_frame_start = None;
+    }
+    /// How many frames have been started
+    pub fn frame_count(&self) -> u32 {
+        self.frames_seen
+    }
+    /// The index of the frame that was most recently started
+    pub fn frame(&self) -> u32 {
+        self.frames_seen.saturating_sub(1)
+    }
+    /// Record how long the GPU took to render the given frame, in
+    /// milliseconds.
+    pub fn record_gpu_time(&mut self, frame: u32, time: f64) {
+        record(&mut self.gpu_pass_times, self.warmup_frames, frame, time);
+    }
+    /// Record how long the CPU spent uploading instances for the given frame,
+    /// in milliseconds.
+    pub fn record_upload_time(&mut self, frame: u32, time: f64) {
+        record(&mut self.upload_times, self.warmup_frames, frame, time);
+    }
+    pub fn surface_events(&self) -> SurfaceEvents {
+        self.surface_events
+    }
+    pub fn surface_events_mut(&mut self) -> &mut SurfaceEvents {
+        &mut self.surface_events
+    }
+    pub fn warmup_frames(&self) -> u32 {
+        self.warmup_frames
+    }
+    /// Every frame with a CPU frame time recorded after the warmup
+    pub fn samples(&self) -> impl Iterator<Item = FrameSample> + '_ {
+        self.cpu_frame_times.iter().enumerate().filter_map(|(index, &cpu_frame_time)| Some(FrameSample {
+            frame: self.warmup_frames + index as u32,
+            cpu_frame_time: cpu_frame_time?,
+            gpu_pass_time: self.gpu_pass_times.get(index).copied().flatten(),
+            upload_time: self.upload_times.get(index).copied().flatten(),
+        }))
+    }
+    pub fn report(&self) -> Option<Summary> {
+        let cpu_frame_times: Vec<f64> = self.cpu_frame_times.iter().flatten().copied().collect();
+        Summary::new(&cpu_frame_times)
+    }
+    pub fn gpu_report(&self) -> Option<Summary> {
+        let gpu_pass_times: Vec<f64> = self.gpu_pass_times.iter().flatten().copied().collect();
+        Summary::new(&gpu_pass_times)
+    }
+    pub fn upload_report(&self) -> Option<Summary> {
+        // Only the frames that have a CPU frame time, so that the uploads
+        // line up with the frames in the other reports
+        let upload_times: Vec<f64> = self.upload_times.iter()
+            .zip(&self.cpu_frame_times)
+            .filter_map(|(&upload_time, cpu_frame_time)| cpu_frame_time.and(upload_time))
+            .collect();
+        Summary::new(&upload_times)
+    }
+    pub fn log_report(&self) {
+        match self.report() {
+            Some(summary) => platform::log(&format!("CPU frame time: {summary}")),
+            None => platform::log("Not enough frames were rendered to report any statistics"),
+        }
+        if let Some(summary) = self.gpu_report() {
+            platform::log(&format!("GPU render pass time: {summary}"));
+        }
+        if let Some(summary) = self.upload_report() {
+            platform::log(&format!("Instance upload time: {summary}"));
+        }
+        if !self.surface_events.is_empty() {
+            platform::log(&format!("Surface events: {}", self.surface_events));
+        }
+    }
+}
+
+/// Frame time statistics, in milliseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+    pub std_dev: f64,
+    pub histogram: Histogram,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    /// The lower bound of the first bucket
+    pub start: f64,
+    pub bucket_width: f64,
+    pub counts: Vec<usize>,
+}
+
+/// The measurements for a single frame, in milliseconds
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FrameSample {
+    pub frame: u32,
+    pub cpu_frame_time: f64,
+    pub gpu_pass_time: Option<f64>,
+    pub upload_time: Option<f64>,
+}
+
+/// Nearest-rank percentile of an already-sorted slice
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    let rank = (percent / 100. * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl Summary {
+    pub fn new(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len();
+        let min = sorted[0];
+        let max = sorted[count - 1];
+        let mean = sorted.iter().sum::<f64>() / count as f64;
+        let variance = sorted.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / count as f64;
+        Some(Self {
+            count,
+            min,
+            mean,
+            median: percentile(&sorted, 50.),
+            p95: percentile(&sorted, 95.),
+            p99: percentile(&sorted, 99.),
+            max,
+            std_dev: variance.sqrt(),
+            histogram: Histogram::new(&sorted, min, max),
+        })
+    }
+}
+
+impl Histogram {
+    fn new(samples: &[f64], min: f64, max: f64) -> Self {
+        // Avoid zero-width buckets if every sample is the same
+        let bucket_width = ((max - min) / HISTOGRAM_BUCKETS as f64).max(f64::EPSILON);
+        let mut counts = vec![0; HISTOGRAM_BUCKETS];
+        samples.iter().for_each(|&t| {
+            let bucket = ((t - min) / bucket_width) as usize;
+            counts[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+        });
+        Self {
+            start: min,
+            bucket_width,
+            counts,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} frames", self.count)?;
+        writeln!(f, "min    {:8.3} ms", self.min)?;
+        writeln!(f, "mean   {:8.3} ms", self.mean)?;
+        writeln!(f, "median {:8.3} ms", self.median)?;
+        writeln!(f, "p95    {:8.3} ms", self.p95)?;
+        writeln!(f, "p99    {:8.3} ms", self.p99)?;
+        writeln!(f, "max    {:8.3} ms", self.max)?;
+        writeln!(f, "stddev {:8.3} ms", self.std_dev)?;
+        write!(f, "{}", self.histogram)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const BAR_WIDTH: usize = 40;
+        let most = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            let low = self.start + bucket as f64 * self.bucket_width;
+            let bar = "#".repeat(count * BAR_WIDTH / most);
+            writeln!(f, "{:8.3} - {:8.3} ms | {:6} {}", low, low + self.bucket_width, count, bar)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_frames_line_up() {
+        let mut stats = FrameStats::new(2);
+        // Frames 0 to 3 are 10 ms apart, then the window is minimized for a
+        // while before frame 4
+        for (frame, now) in [0., 10., 20., 30.].into_iter().enumerate() {
+            stats.begin_frame(now);
+            stats.record_upload_time(frame as u32, frame as f64);
+        }
+        stats.skip_frame();
+        stats.skip_frame();
+        for (frame, now) in [(4, 100.), (5, 105.), (6, 110.)] {
+            stats.begin_frame(now);
+            assert_eq!(stats.frame(), frame);
+            stats.record_upload_time(frame, frame as f64);
+        }
+        (2..7).for_each(|frame| stats.record_gpu_time(frame, frame as f64 / 10.));
+
+        let samples: Vec<(u32, f64, Option<f64>, Option<f64>)> = stats.samples()
+            .map(|sample| (sample.frame, sample.cpu_frame_time, sample.gpu_pass_time, sample.upload_time))
+            .collect();
+        // Frame 3 ended with skipped frames, so it doesn't have a time, and
+        // frame 6 hasn't ended yet
+        assert_eq!(samples, [
+            (2, 10., Some(0.2), Some(2.)),
+            (4, 5., Some(0.4), Some(4.)),
+            (5, 5., Some(0.5), Some(5.)),
+        ]);
+        assert_eq!(stats.report().unwrap().count, 3);
+        assert_eq!(stats.upload_report().unwrap().count, 3);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{error::Error, fmt::Write};
+
+use serde::Serialize;
+
+use crate::{platform, stats::{FrameStats, Summary}};
+
+pub const SWEEP_JSON_OUTPUT: &str = "sweep_output.json";
+pub const SWEEP_CSV_OUTPUT: &str = "sweep_output.csv";
+
+#[derive(Debug, Clone, Copy)]
+pub struct SweepConfig {
+    /// Instance count to start with
+    pub start: u32,
+    /// Don't go past this many instances. `Sweep::new` lowers this to the
+    /// most the device can draw.
+    pub max: u32,
+    /// Frame time budget, in milliseconds
+    pub budget: f64,
+    /// Frames to skip after changing the instance count
+    pub warmup_frames: u32,
+    /// Frames to measure at each instance count
+    pub frames_per_step: u32,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            start: 1,
+            max: 1 << 24,
+            budget: 1000. / 60.,
+            warmup_frames: 10,
+            frames_per_step: 60,
+        }
+    }
+}
+
+/// How long frames took at a particular instance count, in milliseconds
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SweepPoint {
+    pub instance_count: u32,
+    pub median: f64,
+    pub mean: f64,
+    pub p95: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    /// Doubling the instance count until a step goes over budget
+    Growing,
+    /// Binary searching between the largest count known to be under budget,
+    /// and the smallest count known to be over it
+    Searching { low: u32, high: u32 },
+    Done,
+}
+
+/// Finds the largest instance count whose median frame time fits in the
+/// budget. Feed it the start time of every frame, and it says when to change
+/// the instance count.
+pub struct Sweep {
+    config: SweepConfig,
+    phase: Phase,
+    instance_count: u32,
+    step_stats: FrameStats,
+    points: Vec<SweepPoint>,
+    largest_under_budget: Option<u32>,
+}
+
+impl Sweep {
+    /// `max_instances` is the most instances the device can draw. The sweep
+    /// won't go past it, even if the config's `max` is larger.
+    pub fn new(mut config: SweepConfig, max_instances: u32) -> Self {
+        if config.max > max_instances {
+            platform::log(&format!(
+                "The device can't draw more than {max_instances} instances, so the sweep will stop there"));
+            config.max = max_instances;
+        }
+        Self {
+            config,
+            phase: Phase::Growing,
+            instance_count: config.start.clamp(1, config.max.max(1)),
+            step_stats: FrameStats::new(config.warmup_frames),
+            points: Vec::new(),
+            largest_under_budget: None,
+        }
+    }
+    /// The instance count being measured
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+    pub fn done(&self) -> bool {
+        matches!(self.phase, Phase::Done)
+    }
+    /// Call this at the start of every frame. Returns the new instance count
+    /// when it changes.
+    pub fn begin_frame(&mut self, now: f64) -> Option<u32> {
+        if self.done() {
+            return None;
+        }
+        self.step_stats.begin_frame(now);
+        let measured = self.step_stats.frame_count()
+            .saturating_sub(self.config.warmup_frames + 1);
+        if measured < self.config.frames_per_step {
+            return None;
+        }
+        let summary = self.step_stats.report()?;
+        let next = self.next_step(summary);
+        // The frame that just started belongs to the next step
+        self.step_stats = FrameStats::new(self.config.warmup_frames);
+        self.step_stats.begin_frame(now);
+        if let Some(count) = next {
+            self.instance_count = count;
+        }
+        next
+    }
+    /// Call this instead of `begin_frame` when a frame isn't rendered
+    pub fn skip_frame(&mut self) {
+        self.step_stats.skip_frame();
+    }
+    fn next_step(&mut self, summary: Summary) -> Option<u32> {
+        let count = self.instance_count;
+        self.points.push(SweepPoint {
+            instance_count: count,
+            median: summary.median,
+            mean: summary.mean,
+            p95: summary.p95,
+        });
+        let under_budget = summary.median <= self.config.budget;
+        if under_budget {
+            self.largest_under_budget = Some(count);
+        }
+        let (low, high) = match self.phase {
+            Phase::Growing if under_budget => {
+                if count >= self.config.max {
+                    self.phase = Phase::Done;
+                    return None;
+                }
+                return Some(count.saturating_mul(2).min(self.config.max));
+            }
+            Phase::Growing => match self.largest_under_budget {
+                Some(low) => (low, count),
+                // Even the smallest count is over budget
+                None => (0, count),
+            },
+            Phase::Searching { low, .. } if !under_budget => (low, count),
+            Phase::Searching { high, .. } => (count, high),
+            Phase::Done => return None,
+        };
+        // Stop once the gap is down to about 1%
+        if high - low <= (low / 100).max(1) {
+            self.phase = Phase::Done;
+            return None;
+        }
+        self.phase = Phase::Searching { low, high };
+        Some(low + (high - low) / 2)
+    }
+    pub fn report(&self) -> SweepReport {
+        SweepReport {
+            budget: self.config.budget,
+            largest_under_budget: self.largest_under_budget,
+            points: self.points.clone(),
+        }
+    }
+}
+
+/// The curve of instance count against frame time
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepReport {
+    pub budget: f64,
+    pub largest_under_budget: Option<u32>,
+    pub points: Vec<SweepPoint>,
+}
+
+impl SweepReport {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("instance_count,median_ms,mean_ms,p95_ms\n");
+        // The points are in the order they were measured, but a curve is
+        // easier to read in order of instance count.
+        let mut points = self.points.clone();
+        points.sort_by_key(|point| point.instance_count);
+        points.iter().for_each(|point| {
+            let _ = writeln!(csv, "{},{},{},{}", point.instance_count, point.median, point.mean, point.p95);
+        });
+        csv
+    }
+    pub fn log(&self) {
+        let mut text = String::from("instances  median ms  p95 ms\n");
+        let mut points = self.points.clone();
+        points.sort_by_key(|point| point.instance_count);
+        points.iter().for_each(|point| {
+            let _ = writeln!(text, "{:9}  {:9.3}  {:6.3}", point.instance_count, point.median, point.p95);
+        });
+        match self.largest_under_budget {
+            Some(count) => {
+                let _ = write!(text, "Largest instance count within {:.3} ms: {count}", self.budget);
+            }
+            None => {
+                let _ = write!(text, "No instance count fits within {:.3} ms", self.budget);
+            }
+        }
+        platform::log(&text);
+    }
+    pub fn write(&self) -> Result<(), Box<dyn Error>> {
+        platform::write_output(SWEEP_JSON_OUTPUT, &serde_json::to_string_pretty(self)?)?;
+        platform::write_output(SWEEP_CSV_OUTPUT, &self.to_csv())?;
+        Ok(())
+    }
+}
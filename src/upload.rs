@@ -0,0 +1,107 @@
+use std::fmt;
+
+use wgpu::{
+    *,
+    util::{BufferInitDescriptor, DeviceExt, StagingBelt},
+};
+
+use crate::staged_buffer::StagedBuffer;
+
+/// The smallest chunk the staging belt allocates, in bytes
+const STAGING_BELT_CHUNK_SIZE: BufferAddress = 1 << 16;
+
+/// How new instance data gets from the CPU to the GPU every frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UploadStrategy {
+    /// `Queue::write_buffer` straight into the vertex buffer
+    WriteBuffer,
+    /// `Queue::write_buffer` into a staging buffer, which is then copied into
+    /// the vertex buffer
+    #[default]
+    Staged,
+    /// A `StagingBelt` of `MAP_WRITE` buffers, which are copied into the
+    /// vertex buffer
+    StagingBelt,
+    /// A new vertex buffer, made with `create_buffer_init`, every frame
+    Recreate,
+}
+
+impl fmt::Display for UploadStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UploadStrategy::WriteBuffer => "write_buffer",
+            UploadStrategy::Staged => "staged",
+            UploadStrategy::StagingBelt => "staging_belt",
+            UploadStrategy::Recreate => "recreate",
+        })
+    }
+}
+
+/// A vertex buffer, along with whatever the upload strategy needs to fill it
+pub enum InstanceBuffer {
+    WriteBuffer(Buffer),
+    Staged(StagedBuffer),
+    StagingBelt { buffer: Buffer, belt: StagingBelt },
+    Recreate(Buffer),
+}
+
+fn create_buffer(device: &Device, contents: &[u8], usage: BufferUsages) -> Buffer {
+    device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Square instance buffer"),
+        contents,
+        usage,
+    })
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &Device, strategy: UploadStrategy, contents: &[u8]) -> Self {
+        let usage = BufferUsages::VERTEX;
+        match strategy {
+            UploadStrategy::WriteBuffer => InstanceBuffer::WriteBuffer(
+                create_buffer(device, contents, usage | BufferUsages::COPY_DST)),
+            UploadStrategy::Staged => InstanceBuffer::Staged(StagedBuffer::new(device, BufferInitDescriptor {
+                label: Some("Square instance buffer"),
+                contents,
+                usage,
+            })),
+            UploadStrategy::StagingBelt => InstanceBuffer::StagingBelt {
+                buffer: create_buffer(device, contents, usage | BufferUsages::COPY_DST),
+                belt: StagingBelt::new(STAGING_BELT_CHUNK_SIZE.max(contents.len() as BufferAddress)),
+            },
+            UploadStrategy::Recreate => InstanceBuffer::Recreate(create_buffer(device, contents, usage)),
+        }
+    }
+    /// Replace the contents of the buffer. Any copies are recorded into
+    /// `commands`, so they happen before anything else recorded after this.
+    pub fn upload(&mut self, device: &Device, queue: &Queue, commands: &mut CommandEncoder, contents: &[u8]) {
+        match self {
+            InstanceBuffer::WriteBuffer(buffer) => queue.write_buffer(buffer, 0, contents),
+            InstanceBuffer::Staged(buffer) => {
+                buffer.stage(queue, 0, contents);
+                buffer.gpu_copy(commands);
+            }
+            InstanceBuffer::StagingBelt { buffer, belt } => {
+                // The belt can't write zero bytes
+                if let Some(size) = BufferSize::new(contents.len() as BufferAddress) {
+                    belt.write_buffer(commands, buffer, 0, size, device).copy_from_slice(contents);
+                }
+                belt.finish();
+            }
+            InstanceBuffer::Recreate(buffer) => *buffer = create_buffer(device, contents, BufferUsages::VERTEX),
+        }
+    }
+    /// Call this once the commands passed to `upload` have been submitted
+    pub fn after_submit(&mut self) {
+        if let InstanceBuffer::StagingBelt { belt, .. } = self {
+            belt.recall();
+        }
+    }
+    pub fn buffer(&self) -> &Buffer {
+        match self {
+            InstanceBuffer::WriteBuffer(buffer) |
+            InstanceBuffer::StagingBelt { buffer, .. } |
+            InstanceBuffer::Recreate(buffer) => buffer,
+            InstanceBuffer::Staged(buffer) => buffer,
+        }
+    }
+}
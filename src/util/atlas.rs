@@ -0,0 +1,155 @@
+use std::error::Error;
+
+use image::{imageops, DynamicImage, ImageBuffer, Pixel};
+
+use super::{compressed::LoadedImage, context::GpuContext, mipmap::MipGenerator, texture::Texture};
+
+/// Space around each image, in pixels. Every mip level halves it, so the
+/// images are kept from bleeding into each other on the first four mip
+/// levels.
+pub const ATLAS_PADDING: u32 = 8;
+/// How many mip levels an atlas has. The smaller levels would have less than
+/// a pixel of gutter, so they're left out.
+pub const ATLAS_MIP_LEVELS: u32 = ATLAS_PADDING.ilog2() + 1;
+
+/// Where an image is in an atlas, in UV coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+}
+
+/// Several images packed into one texture
+pub struct Atlas {
+    pub texture: Texture,
+    /// One region for each image, in the order they were given
+    pub regions: Vec<AtlasRegion>,
+}
+
+/// Where each image goes in the atlas, in pixels, and how big the atlas is
+struct Packing {
+    width: u32,
+    height: u32,
+    positions: Vec<(u32, u32)>,
+}
+
+/// Shelf packing: the images go in rows, tallest first, and a new row is
+/// started when a row is full. Every image gets `ATLAS_PADDING` pixels of
+/// gutter on each side, and starts on a multiple of `ATLAS_PADDING`, so that
+/// the images stay on pixel boundaries on the first four mip levels.
+fn pack(sizes: &[(u32, u32)]) -> Packing {
+    let align = |value: u32| value.div_ceil(ATLAS_PADDING) * ATLAS_PADDING;
+    let padded: Vec<(u32, u32)> = sizes.iter()
+        .map(|&(width, height)| (align(width + 2 * ATLAS_PADDING), align(height + 2 * ATLAS_PADDING)))
+        .collect();
+    let area: u64 = padded.iter().map(|&(width, height)| width as u64 * height as u64).sum();
+    let widest = padded.iter().map(|&(width, _)| width).max().unwrap_or(ATLAS_PADDING);
+    let width = align(((area as f64).sqrt().ceil() as u32).max(widest));
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(padded[index].1));
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for index in order {
+        let (padded_width, padded_height) = padded[index];
+        if x + padded_width > width {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        positions[index] = (x + ATLAS_PADDING, y + ATLAS_PADDING);
+        x += padded_width;
+        shelf_height = shelf_height.max(padded_height);
+    }
+    Packing {
+        width,
+        height: (y + shelf_height).max(ATLAS_PADDING),
+        positions,
+    }
+}
+
+/// Copy the images into place, and stretch the edge pixels of each image out
+/// into its gutter.
+fn compose<P: Pixel>(
+    images: &[ImageBuffer<P, Vec<P::Subpixel>>],
+    packing: &Packing,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let mut atlas = ImageBuffer::new(packing.width, packing.height);
+    images.iter().zip(&packing.positions).for_each(|(image, &(x, y))| {
+        imageops::replace(&mut atlas, image, x as i64, y as i64);
+        let (width, height) = image.dimensions();
+        let gutter_x = x.saturating_sub(ATLAS_PADDING)..(x + width + ATLAS_PADDING).min(packing.width);
+        let gutter_y = y.saturating_sub(ATLAS_PADDING)..(y + height + ATLAS_PADDING).min(packing.height);
+        for atlas_y in gutter_y {
+            for atlas_x in gutter_x.clone() {
+                let inside_x = (x..x + width).contains(&atlas_x);
+                let inside_y = (y..y + height).contains(&atlas_y);
+                if inside_x && inside_y {
+                    continue;
+                }
+                let source_x = atlas_x.clamp(x, x + width - 1) - x;
+                let source_y = atlas_y.clamp(y, y + height - 1) - y;
+                atlas.put_pixel(atlas_x, atlas_y, *image.get_pixel(source_x, source_y));
+            }
+        }
+    });
+    atlas
+}
+
+/// Whether an image has more than 8 bits per channel
+fn is_high_precision(image: &DynamicImage) -> bool {
+    !matches!(
+        image,
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) |
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)
+    )
+}
+
+impl Atlas {
+    /// Pack several images into one texture. If `premultiply` is true, the
+    /// colour channels are multiplied by alpha before the texture is
+    /// uploaded. See `Texture::from_loaded` for `mip_generator`.
+    /// Block-compressed images are always decompressed, since they're
+    /// packed on the CPU, and they're decompressed in place, like in
+    /// `Texture::from_loaded`.
+    pub fn from_loaded(
+        context: &GpuContext,
+        images: &mut [LoadedImage],
+        label: Option<&str>,
+        premultiply: bool,
+        mip_generator: Option<&MipGenerator>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let images = images.iter_mut()
+            .map(|image| image.decode().cloned())
+            .collect::<Result<_, _>>()?;
+        Atlas::from_images(context, images, label, premultiply, mip_generator)
+    }
+    pub fn from_images(
+        context: &GpuContext,
+        images: Vec<DynamicImage>,
+        label: Option<&str>,
+        premultiply: bool,
+        mip_generator: Option<&MipGenerator>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if images.is_empty() {
+            return Err(Box::from("An atlas needs at least one image"));
+        }
+        let sizes: Vec<(u32, u32)> = images.iter().map(|image| (image.width(), image.height())).collect();
+        let packing = pack(&sizes);
+        // Keep the extra precision if any of the images have it
+        let atlas = if images.iter().any(is_high_precision) {
+            let images: Vec<_> = images.into_iter().map(DynamicImage::into_rgba16).collect();
+            DynamicImage::ImageRgba16(compose(&images, &packing))
+        } else {
+            let images: Vec<_> = images.into_iter().map(DynamicImage::into_rgba8).collect();
+            DynamicImage::ImageRgba8(compose(&images, &packing))
+        };
+        let (atlas_width, atlas_height) = (packing.width as f32, packing.height as f32);
+        let regions = sizes.iter().zip(&packing.positions).map(|(&(width, height), &(x, y))| AtlasRegion {
+            offset: [x as f32 / atlas_width, y as f32 / atlas_height],
+            size: [width as f32 / atlas_width, height as f32 / atlas_height],
+        }).collect();
+        let texture = Texture::from_images_with_mip_limit(
+            context, vec![atlas], label, premultiply, mip_generator, ATLAS_MIP_LEVELS)?;
+        Ok(Atlas { texture, regions })
+    }
+}
@@ -0,0 +1,519 @@
+use std::error::Error;
+
+use ddsfile::{D3DFormat, Dds, DxgiFormat};
+use image::{DynamicImage, Rgba, RgbaImage};
+use ktx2::Format;
+use wgpu::{AstcBlock, AstcChannel, Device, Extent3d, TextureFormat};
+
+use crate::platform;
+
+use super::texture::linear_to_srgb;
+
+/// The first bytes of every KTX2 file
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+/// The first bytes of every DDS file
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+/// A block-compressed image from a KTX2 or DDS file, with the mips that came
+/// with it
+pub struct CompressedImage {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// The data for each mip level, largest first. There's always at least
+    /// one.
+    pub levels: Vec<Vec<u8>>,
+}
+
+/// An image file, which might be block-compressed
+pub enum LoadedImage {
+    Compressed(CompressedImage),
+    Decoded(DynamicImage),
+}
+
+impl LoadedImage {
+    /// Load a KTX2 or DDS file, or anything the `image` crate can read
+    pub fn load(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if data.starts_with(&KTX2_IDENTIFIER) {
+            Ok(LoadedImage::Compressed(CompressedImage::from_ktx2(data)?))
+        } else if data.starts_with(&DDS_MAGIC) {
+            Ok(LoadedImage::Compressed(CompressedImage::from_dds(data)?))
+        } else {
+            Ok(LoadedImage::Decoded(image::load_from_memory(data)?))
+        }
+    }
+    /// Load every file in `paths`, in order
+    pub async fn load_assets(paths: &[impl AsRef<str>]) -> Result<Vec<Self>, Box<dyn Error>> {
+        let mut images = Vec::with_capacity(paths.len());
+        for path in paths {
+            let asset_data = platform::read_asset(path.as_ref()).await?;
+            images.push(LoadedImage::load(&asset_data)?);
+        }
+        Ok(images)
+    }
+    /// The image, decompressed if it has to be. It's only decompressed once;
+    /// the decompressed image replaces the compressed one.
+    pub fn decode(&mut self) -> Result<&DynamicImage, Box<dyn Error>> {
+        if let LoadedImage::Compressed(image) = self {
+            *self = LoadedImage::Decoded(image.decompress()?);
+        }
+        match self {
+            LoadedImage::Decoded(image) => Ok(image),
+            LoadedImage::Compressed(_) => unreachable!(),
+        }
+    }
+}
+
+/// Check whether a set of images can go into one texture as they are. They
+/// all have to be compressed, in the same format, at the same size, and the
+/// device has to support the format. If they can't, the reason why is
+/// returned.
+pub fn uploadable<'a>(device: &Device, images: &'a [LoadedImage]) -> Result<Vec<&'a CompressedImage>, String> {
+    let compressed: Vec<&CompressedImage> = images.iter().filter_map(|image| match image {
+        LoadedImage::Compressed(image) => Some(image),
+        LoadedImage::Decoded(_) => None,
+    }).collect();
+    let Some(first) = compressed.first() else {
+        return Err(String::from("None of the textures are block-compressed"));
+    };
+    if compressed.len() != images.len() {
+        return Err(String::from("Some of the textures aren't block-compressed"));
+    }
+    if compressed.iter().any(|image| (image.format, image.width, image.height) != (first.format, first.width, first.height)) {
+        return Err(String::from("The block-compressed textures aren't all the same format and size"));
+    }
+    let format = first.format;
+    if !device.features().contains(format.required_features()) {
+        return Err(format!("This device doesn't support {format:?} textures"));
+    }
+    let (block_width, block_height) = format.block_dimensions();
+    if first.width % block_width != 0 || first.height % block_height != 0 {
+        return Err(format!("{format:?} textures have to be a whole number of blocks across"));
+    }
+    Ok(compressed)
+}
+
+/// The size of a mip level, rounded up to whole blocks
+fn level_extent(format: TextureFormat, width: u32, height: u32, level: u32) -> Extent3d {
+    Extent3d {
+        width: (width >> level).max(1),
+        height: (height >> level).max(1),
+        depth_or_array_layers: 1,
+    }.physical_size(format)
+}
+
+/// How many bytes are in each row of blocks in a mip level, and how many rows
+/// there are
+fn level_layout(format: TextureFormat, width: u32, height: u32, level: u32) -> (u32, u32) {
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_size(None).unwrap_or(0);
+    let extent = level_extent(format, width, height, level);
+    (extent.width / block_width * block_size, extent.height / block_height)
+}
+
+fn level_size(format: TextureFormat, width: u32, height: u32, level: u32) -> usize {
+    let (bytes_per_row, rows) = level_layout(format, width, height, level);
+    bytes_per_row as usize * rows as usize
+}
+
+/// The texture format for a KTX2 format, if it's block-compressed
+fn ktx2_format(format: Format) -> Option<TextureFormat> {
+    let astc = |block, channel| TextureFormat::Astc { block, channel };
+    Some(match format {
+        Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGBA_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+        Format::BC1_RGB_SRGB_BLOCK | Format::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        Format::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+        Format::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        Format::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        Format::BC4_UNORM_BLOCK => TextureFormat::Bc4RUnorm,
+        Format::BC4_SNORM_BLOCK => TextureFormat::Bc4RSnorm,
+        Format::BC5_UNORM_BLOCK => TextureFormat::Bc5RgUnorm,
+        Format::BC5_SNORM_BLOCK => TextureFormat::Bc5RgSnorm,
+        Format::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+        Format::BC6H_SFLOAT_BLOCK => TextureFormat::Bc6hRgbFloat,
+        Format::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        Format::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+        Format::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK => TextureFormat::Etc2Rgb8A1Unorm,
+        Format::ETC2_R8G8B8A1_SRGB_BLOCK => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+        Format::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+        Format::EAC_R11_UNORM_BLOCK => TextureFormat::EacR11Unorm,
+        Format::EAC_R11_SNORM_BLOCK => TextureFormat::EacR11Snorm,
+        Format::EAC_R11G11_UNORM_BLOCK => TextureFormat::EacRg11Unorm,
+        Format::EAC_R11G11_SNORM_BLOCK => TextureFormat::EacRg11Snorm,
+        Format::ASTC_4x4_UNORM_BLOCK => astc(AstcBlock::B4x4, AstcChannel::Unorm),
+        Format::ASTC_4x4_SRGB_BLOCK => astc(AstcBlock::B4x4, AstcChannel::UnormSrgb),
+        Format::ASTC_5x4_UNORM_BLOCK => astc(AstcBlock::B5x4, AstcChannel::Unorm),
+        Format::ASTC_5x4_SRGB_BLOCK => astc(AstcBlock::B5x4, AstcChannel::UnormSrgb),
+        Format::ASTC_5x5_UNORM_BLOCK => astc(AstcBlock::B5x5, AstcChannel::Unorm),
+        Format::ASTC_5x5_SRGB_BLOCK => astc(AstcBlock::B5x5, AstcChannel::UnormSrgb),
+        Format::ASTC_6x5_UNORM_BLOCK => astc(AstcBlock::B6x5, AstcChannel::Unorm),
+        Format::ASTC_6x5_SRGB_BLOCK => astc(AstcBlock::B6x5, AstcChannel::UnormSrgb),
+        Format::ASTC_6x6_UNORM_BLOCK => astc(AstcBlock::B6x6, AstcChannel::Unorm),
+        Format::ASTC_6x6_SRGB_BLOCK => astc(AstcBlock::B6x6, AstcChannel::UnormSrgb),
+        Format::ASTC_8x5_UNORM_BLOCK => astc(AstcBlock::B8x5, AstcChannel::Unorm),
+        Format::ASTC_8x5_SRGB_BLOCK => astc(AstcBlock::B8x5, AstcChannel::UnormSrgb),
+        Format::ASTC_8x6_UNORM_BLOCK => astc(AstcBlock::B8x6, AstcChannel::Unorm),
+        Format::ASTC_8x6_SRGB_BLOCK => astc(AstcBlock::B8x6, AstcChannel::UnormSrgb),
+        Format::ASTC_8x8_UNORM_BLOCK => astc(AstcBlock::B8x8, AstcChannel::Unorm),
+        Format::ASTC_8x8_SRGB_BLOCK => astc(AstcBlock::B8x8, AstcChannel::UnormSrgb),
+        Format::ASTC_10x5_UNORM_BLOCK => astc(AstcBlock::B10x5, AstcChannel::Unorm),
+        Format::ASTC_10x5_SRGB_BLOCK => astc(AstcBlock::B10x5, AstcChannel::UnormSrgb),
+        Format::ASTC_10x6_UNORM_BLOCK => astc(AstcBlock::B10x6, AstcChannel::Unorm),
+        Format::ASTC_10x6_SRGB_BLOCK => astc(AstcBlock::B10x6, AstcChannel::UnormSrgb),
+        Format::ASTC_10x8_UNORM_BLOCK => astc(AstcBlock::B10x8, AstcChannel::Unorm),
+        Format::ASTC_10x8_SRGB_BLOCK => astc(AstcBlock::B10x8, AstcChannel::UnormSrgb),
+        Format::ASTC_10x10_UNORM_BLOCK => astc(AstcBlock::B10x10, AstcChannel::Unorm),
+        Format::ASTC_10x10_SRGB_BLOCK => astc(AstcBlock::B10x10, AstcChannel::UnormSrgb),
+        Format::ASTC_12x10_UNORM_BLOCK => astc(AstcBlock::B12x10, AstcChannel::Unorm),
+        Format::ASTC_12x10_SRGB_BLOCK => astc(AstcBlock::B12x10, AstcChannel::UnormSrgb),
+        Format::ASTC_12x12_UNORM_BLOCK => astc(AstcBlock::B12x12, AstcChannel::Unorm),
+        Format::ASTC_12x12_SRGB_BLOCK => astc(AstcBlock::B12x12, AstcChannel::UnormSrgb),
+        _ => return None,
+    })
+}
+
+/// The texture format for a DX10-style DDS format, if it's block-compressed
+fn dxgi_format(format: DxgiFormat) -> Option<TextureFormat> {
+    Some(match format {
+        DxgiFormat::BC1_Typeless | DxgiFormat::BC1_UNorm => TextureFormat::Bc1RgbaUnorm,
+        DxgiFormat::BC1_UNorm_sRGB => TextureFormat::Bc1RgbaUnormSrgb,
+        DxgiFormat::BC2_Typeless | DxgiFormat::BC2_UNorm => TextureFormat::Bc2RgbaUnorm,
+        DxgiFormat::BC2_UNorm_sRGB => TextureFormat::Bc2RgbaUnormSrgb,
+        DxgiFormat::BC3_Typeless | DxgiFormat::BC3_UNorm => TextureFormat::Bc3RgbaUnorm,
+        DxgiFormat::BC3_UNorm_sRGB => TextureFormat::Bc3RgbaUnormSrgb,
+        DxgiFormat::BC4_Typeless | DxgiFormat::BC4_UNorm => TextureFormat::Bc4RUnorm,
+        DxgiFormat::BC4_SNorm => TextureFormat::Bc4RSnorm,
+        DxgiFormat::BC5_Typeless | DxgiFormat::BC5_UNorm => TextureFormat::Bc5RgUnorm,
+        DxgiFormat::BC5_SNorm => TextureFormat::Bc5RgSnorm,
+        DxgiFormat::BC6H_Typeless | DxgiFormat::BC6H_UF16 => TextureFormat::Bc6hRgbUfloat,
+        DxgiFormat::BC6H_SF16 => TextureFormat::Bc6hRgbFloat,
+        DxgiFormat::BC7_Typeless | DxgiFormat::BC7_UNorm => TextureFormat::Bc7RgbaUnorm,
+        DxgiFormat::BC7_UNorm_sRGB => TextureFormat::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+/// The texture format for an old-style DDS format, if it's block-compressed.
+/// These don't say what colour space they're in, so they're taken to be
+/// sRGB, like 8-bit PNGs are.
+fn d3d_format(format: D3DFormat) -> Option<TextureFormat> {
+    Some(match format {
+        D3DFormat::DXT1 => TextureFormat::Bc1RgbaUnormSrgb,
+        D3DFormat::DXT2 | D3DFormat::DXT3 => TextureFormat::Bc2RgbaUnormSrgb,
+        D3DFormat::DXT4 | D3DFormat::DXT5 => TextureFormat::Bc3RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+/// Decode one channel of a signed BC4 block into `pixels`, which are packed
+/// as BGRA. The values are mapped from -1..1 to 0..255, the same way the
+/// signed EAC decoders do it.
+fn decode_signed_bc4_channel(block: &[u8], channel: usize, pixels: &mut [u32; 16]) {
+    // -128 means the same as -127
+    let [red0, red1] = [block[0], block[1]].map(|value| (value as i8).max(-127) as f32);
+    let mut values = [red0, red1, 0., 0., 0., 0., -127., 127.];
+    if red0 > red1 {
+        (1..7).for_each(|step| values[step + 1] = (red0 * (7 - step) as f32 + red1 * step as f32) / 7.);
+    } else {
+        (1..5).for_each(|step| values[step + 1] = (red0 * (5 - step) as f32 + red1 * step as f32) / 5.);
+    }
+    let mut indices = u64::from_le_bytes(block[..8].try_into().unwrap()) >> 16;
+    let shift = channel * 8;
+    pixels.iter_mut().for_each(|pixel| {
+        let value = ((values[indices as usize & 7] + 127.) * 255. / 254.).round() as u32;
+        *pixel = (*pixel & !(0xFF << shift)) | value << shift;
+        indices >>= 3;
+    });
+}
+
+/// Decode signed BC4 (one channel) or BC5 (two channels) data, like the
+/// unsigned decoders in `texture2ddecoder`
+fn decode_signed_bc(data: &[u8], width: usize, height: usize, channels: usize, image: &mut [u32]) -> Result<(), &'static str> {
+    let block_size = 8 * channels;
+    let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+    if data.len() < blocks_x * blocks_y * block_size {
+        return Err("Not enough data to decode image!");
+    }
+    if image.len() < width * height {
+        return Err("Image buffer is too small!");
+    }
+    // Blue is always 0, and the image is opaque
+    let mut pixels = [0xFF00_0000; 16];
+    data.chunks_exact(block_size).take(blocks_x * blocks_y).enumerate().for_each(|(index, block)| {
+        // Red, then green
+        decode_signed_bc4_channel(block, 2, &mut pixels);
+        if channels == 2 {
+            decode_signed_bc4_channel(&block[8..], 1, &mut pixels);
+        }
+        let (block_x, block_y) = (index % blocks_x * 4, index / blocks_x * 4);
+        for y in (0..4).filter(|y| block_y + y < height) {
+            for x in (0..4).filter(|x| block_x + x < width) {
+                image[(block_y + y) * width + block_x + x] = pixels[y * 4 + x];
+            }
+        }
+    });
+    Ok(())
+}
+
+fn decode_bc4_signed(data: &[u8], width: usize, height: usize, image: &mut [u32]) -> Result<(), &'static str> {
+    decode_signed_bc(data, width, height, 1, image)
+}
+
+fn decode_bc5_signed(data: &[u8], width: usize, height: usize, image: &mut [u32]) -> Result<(), &'static str> {
+    decode_signed_bc(data, width, height, 2, image)
+}
+
+impl CompressedImage {
+    fn from_ktx2(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let reader = ktx2::Reader::new(data)?;
+        let header = reader.header();
+        if header.supercompression_scheme.is_some() {
+            return Err(Box::from("Supercompressed KTX2 files aren't supported"));
+        }
+        if header.pixel_depth > 1 || header.face_count != 1 {
+            return Err(Box::from("Only 2D KTX2 textures are supported"));
+        }
+        let format = header.format
+            .and_then(ktx2_format)
+            .ok_or_else(|| format!("{:?} isn't a supported block-compressed format", header.format))?;
+        // Only the first layer is used, which comes first in each level
+        let levels = reader.levels().map(|level| level.data);
+        CompressedImage::new(format, header.pixel_width, header.pixel_height.max(1), levels)
+    }
+    fn from_dds(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let dds = Dds::read(data)?;
+        if dds.get_depth() > 1 {
+            return Err(Box::from("Only 2D DDS textures are supported"));
+        }
+        let format = dds.get_dxgi_format()
+            .and_then(dxgi_format)
+            .or_else(|| dds.get_d3d_format().and_then(d3d_format))
+            .ok_or("The DDS file isn't in a supported block-compressed format")?;
+        // The levels of each layer are packed one after the other. Only the
+        // first layer is used, which comes first. `get_data` isn't used, so
+        // that a file that's cut short still has the levels that fit.
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let mut data = dds.data.as_slice();
+        let levels = (0..dds.get_num_mipmap_levels()).map_while(|level| {
+            let (level, rest) = data.split_at_checked(level_size(format, width, height, level))?;
+            data = rest;
+            Some(level)
+        });
+        CompressedImage::new(format, width, height, levels)
+    }
+    /// Keep as many levels as there's enough data for. There has to be
+    /// enough for at least one.
+    fn new<'a>(
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        levels: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let levels: Vec<Vec<u8>> = levels.into_iter()
+            .enumerate()
+            .map_while(|(level, data)| data.get(..level_size(format, width, height, level as u32)))
+            .map(<[u8]>::to_vec)
+            .collect();
+        if levels.is_empty() {
+            return Err(Box::from("The file doesn't have enough data for the first mip level"));
+        }
+        Ok(CompressedImage { format, width, height, levels })
+    }
+    pub fn level_extent(&self, level: u32) -> Extent3d {
+        level_extent(self.format, self.width, self.height, level)
+    }
+    pub fn level_layout(&self, level: u32) -> (u32, u32) {
+        level_layout(self.format, self.width, self.height, level)
+    }
+    /// Decode the first level into an 8-bit sRGB image, for devices that
+    /// don't support the format. Unsigned linear formats are encoded as sRGB,
+    /// so apart from rounding they look the same when they're sampled. Other
+    /// formats don't: signed formats are mapped from -1..1 to 0..1, and BC6H
+    /// is clipped to 0..1 and rounded to 8 bits.
+    pub fn decompress(&self) -> Result<DynamicImage, Box<dyn Error>> {
+        use texture2ddecoder::*;
+        let (width, height) = (self.width as usize, self.height as usize);
+        let data = &self.levels[0];
+        let mut pixels = vec![0; width * height];
+        match self.format {
+            TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => decode_bc1a(data, width, height, &mut pixels),
+            TextureFormat::Bc2RgbaUnorm | TextureFormat::Bc2RgbaUnormSrgb => decode_bc2(data, width, height, &mut pixels),
+            TextureFormat::Bc3RgbaUnorm | TextureFormat::Bc3RgbaUnormSrgb => decode_bc3(data, width, height, &mut pixels),
+            TextureFormat::Bc4RUnorm => decode_bc4(data, width, height, &mut pixels),
+            TextureFormat::Bc4RSnorm => decode_bc4_signed(data, width, height, &mut pixels),
+            TextureFormat::Bc5RgUnorm => decode_bc5(data, width, height, &mut pixels),
+            TextureFormat::Bc5RgSnorm => decode_bc5_signed(data, width, height, &mut pixels),
+            TextureFormat::Bc6hRgbUfloat => decode_bc6_unsigned(data, width, height, &mut pixels),
+            TextureFormat::Bc6hRgbFloat => decode_bc6_signed(data, width, height, &mut pixels),
+            TextureFormat::Bc7RgbaUnorm | TextureFormat::Bc7RgbaUnormSrgb => decode_bc7(data, width, height, &mut pixels),
+            TextureFormat::Etc2Rgb8Unorm | TextureFormat::Etc2Rgb8UnormSrgb => decode_etc2_rgb(data, width, height, &mut pixels),
+            TextureFormat::Etc2Rgb8A1Unorm | TextureFormat::Etc2Rgb8A1UnormSrgb => decode_etc2_rgba1(data, width, height, &mut pixels),
+            TextureFormat::Etc2Rgba8Unorm | TextureFormat::Etc2Rgba8UnormSrgb => decode_etc2_rgba8(data, width, height, &mut pixels),
+            TextureFormat::EacR11Unorm => decode_eacr(data, width, height, &mut pixels),
+            TextureFormat::EacR11Snorm => decode_eacr_signed(data, width, height, &mut pixels),
+            TextureFormat::EacRg11Unorm => decode_eacrg(data, width, height, &mut pixels),
+            TextureFormat::EacRg11Snorm => decode_eacrg_signed(data, width, height, &mut pixels),
+            TextureFormat::Astc { .. } => {
+                let (block_width, block_height) = self.format.block_dimensions();
+                decode_astc(data, width, height, block_width as usize, block_height as usize, &mut pixels)
+            }
+            format => return Err(Box::from(format!("{format:?} textures can't be decompressed"))),
+        }?;
+        let srgb = self.format.is_srgb();
+        // Formats without an alpha channel are opaque when they're sampled
+        let opaque = matches!(self.format,
+            TextureFormat::Bc4RUnorm | TextureFormat::Bc4RSnorm |
+            TextureFormat::Bc5RgUnorm | TextureFormat::Bc5RgSnorm |
+            TextureFormat::Bc6hRgbUfloat | TextureFormat::Bc6hRgbFloat |
+            TextureFormat::Etc2Rgb8Unorm | TextureFormat::Etc2Rgb8UnormSrgb |
+            TextureFormat::EacR11Unorm | TextureFormat::EacR11Snorm |
+            TextureFormat::EacRg11Unorm | TextureFormat::EacRg11Snorm);
+        let image = RgbaImage::from_fn(self.width, self.height, |x, y| {
+            // The decoder packs pixels as BGRA
+            let [b, g, r, a] = pixels[y as usize * width + x as usize].to_le_bytes();
+            let channel = |value: u8| match srgb {
+                true => value,
+                false => (linear_to_srgb(value as f32 / 255.) * 255.).round() as u8,
+            };
+            Rgba([channel(r), channel(g), channel(b), if opaque { 255 } else { a }])
+        });
+        Ok(DynamicImage::ImageRgba8(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ddsfile::{AlphaMode, D3D10ResourceDimension, NewDxgiParams};
+
+    use super::*;
+
+    /// A KTX2 file with no key/value data and an empty data format descriptor
+    fn ktx2_file(format: u32, width: u32, height: u32, levels: &[&[u8]]) -> Vec<u8> {
+        let dfd_offset = 80 + 24 * levels.len() as u32;
+        let mut header = KTX2_IDENTIFIER.to_vec();
+        // Format, type size, width, height, depth, layers, faces, levels,
+        // supercompression, and where the DFD is
+        [format, 1, width, height, 0, 0, 1, levels.len() as u32, 0, dfd_offset, 4]
+            .iter()
+            .for_each(|value| header.extend(value.to_le_bytes()));
+        // No key/value data or supercompression global data
+        header.resize(80, 0);
+        let mut data = Vec::new();
+        let mut level_offset = dfd_offset as u64 + 4;
+        for level in levels {
+            let length = level.len() as u64;
+            [level_offset, length, length].iter().for_each(|value| header.extend(value.to_le_bytes()));
+            data.extend_from_slice(level);
+            level_offset += length;
+        }
+        header.extend(4u32.to_le_bytes());
+        header.extend(data);
+        header
+    }
+
+    fn dds_file(format: DxgiFormat, width: u32, height: u32, mipmap_levels: u32) -> Vec<u8> {
+        let mut dds = Dds::new_dxgi(NewDxgiParams {
+            height,
+            width,
+            depth: None,
+            format,
+            mipmap_levels: Some(mipmap_levels),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Unknown,
+        }).unwrap();
+        dds.data.iter_mut().enumerate().for_each(|(index, byte)| *byte = index as u8);
+        let mut file = Vec::new();
+        dds.write(&mut file).unwrap();
+        file
+    }
+
+    fn compressed(image: LoadedImage) -> CompressedImage {
+        match image {
+            LoadedImage::Compressed(image) => image,
+            LoadedImage::Decoded(_) => panic!("The image was decoded"),
+        }
+    }
+
+    #[test]
+    fn level_sizes() {
+        // Levels smaller than a block still take up a whole block
+        let bc1: Vec<usize> = (0..4).map(|level| level_size(TextureFormat::Bc1RgbaUnorm, 8, 8, level)).collect();
+        assert_eq!(bc1, [32, 8, 8, 8]);
+        assert_eq!(level_size(TextureFormat::Bc7RgbaUnorm, 12, 4, 0), 48);
+        assert_eq!(level_layout(TextureFormat::Bc7RgbaUnorm, 12, 4, 0), (48, 1));
+        let astc = TextureFormat::Astc { block: AstcBlock::B5x5, channel: AstcChannel::Unorm };
+        assert_eq!(level_layout(astc, 12, 12, 0), (48, 3));
+        assert_eq!(level_size(astc, 12, 12, 1), 64);
+    }
+
+    #[test]
+    fn ktx2_levels() {
+        let levels: [&[u8]; 3] = [&[1; 32], &[2; 8], &[3; 8]];
+        let file = ktx2_file(133, 8, 8, &levels);
+        let image = compressed(LoadedImage::load(&file).unwrap());
+        assert_eq!((image.format, image.width, image.height), (TextureFormat::Bc1RgbaUnorm, 8, 8));
+        assert_eq!(image.levels, levels);
+        // Levels without enough data are left out
+        let file = ktx2_file(140, 8, 8, &[&[4; 32], &[5; 4]]);
+        let image = compressed(LoadedImage::load(&file).unwrap());
+        assert_eq!(image.format, TextureFormat::Bc4RSnorm);
+        assert_eq!(image.levels, [[4; 32]]);
+        // Uncompressed formats aren't supported (37 is R8G8B8A8_UNORM)
+        assert!(LoadedImage::load(&ktx2_file(37, 1, 1, &[&[0; 4]])).is_err());
+    }
+
+    #[test]
+    fn dds_levels() {
+        let file = dds_file(DxgiFormat::BC1_UNorm, 8, 8, 4);
+        let image = compressed(LoadedImage::load(&file).unwrap());
+        assert_eq!((image.format, image.width, image.height), (TextureFormat::Bc1RgbaUnorm, 8, 8));
+        let sizes: Vec<usize> = image.levels.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [32, 8, 8, 8]);
+        // Each level starts where the last one ended
+        assert_eq!(image.levels[1][0], 32);
+        assert_eq!(image.levels[3][0], 48);
+        // Levels cut off at the end of the file are left out
+        let image = compressed(LoadedImage::load(&file[..file.len() - 1]).unwrap());
+        assert_eq!(image.levels.len(), 3);
+        let file = dds_file(DxgiFormat::BC5_SNorm, 4, 4, 1);
+        assert_eq!(compressed(LoadedImage::load(&file).unwrap()).format, TextureFormat::Bc5RgSnorm);
+    }
+
+    #[test]
+    fn signed_bc4_and_bc5() {
+        // Red goes from 127 (1.0) to -127 (-1.0). The first pixel uses index
+        // 0, the second uses index 1, and the third uses index 2, which is
+        // 6/7 of the way from -1 to 1.
+        let red = [127, 0x81, 0x88, 0, 0, 0, 0, 0];
+        // When the first endpoint isn't bigger than the second, indices 6
+        // and 7 are -1 and 1. -128 is the same as -127, so the first pixel
+        // is -1, and the last is 0.
+        let green = [0x80, 0, 0xF0, 0x03, 0, 0, 0, 0];
+        let channel = |pixel: u32, channel: usize| (pixel >> (channel * 8)) as u8;
+
+        let mut pixels = [0; 16];
+        decode_bc4_signed(&red, 4, 4, &mut pixels).unwrap();
+        let reds: Vec<u8> = pixels.iter().map(|&pixel| channel(pixel, 2)).collect();
+        assert_eq!(reds[..4], [255, 0, 219, 255]);
+        assert!(pixels.iter().all(|&pixel| pixel & 0xFF00_FFFF == 0xFF00_0000));
+
+        let mut pixels = [0; 16];
+        decode_bc5_signed(&[red, green].concat(), 4, 4, &mut pixels).unwrap();
+        let greens: Vec<u8> = pixels.iter().map(|&pixel| channel(pixel, 1)).collect();
+        assert_eq!(greens[..4], [0, 0, 255, 128]);
+        assert_eq!(channel(pixels[2], 2), 219);
+
+        // Blocks are cropped to the size of the image
+        let mut pixels = [0; 4];
+        decode_bc4_signed(&red, 2, 2, &mut pixels).unwrap();
+        assert_eq!(pixels.map(|pixel| channel(pixel, 2)), [255, 0, 255, 255]);
+        assert!(decode_bc5_signed(&red, 4, 4, &mut [0; 16]).is_err());
+    }
+}
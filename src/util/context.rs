@@ -0,0 +1,243 @@
+use std::{error::Error, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+use wgpu::*;
+
+use crate::{config::BenchConfig, platform};
+
+use super::texture::DEPTH_FORMAT;
+
+/// Sample counts that can be asked for, from most to fewest
+pub const SAMPLE_COUNTS: [u32; 4] = [8, 4, 2, 1];
+/// What wgpu says when something fails because the device has been lost
+const DEVICE_LOST_MESSAGE: &str = "Parent device is lost";
+
+/// Whether an error message from wgpu means that the device has been lost.
+/// wgpu 0.17 doesn't have a device lost callback, so this is the only way to
+/// tell.
+pub fn is_device_lost_error(message: &str) -> bool {
+    message.contains(DEVICE_LOST_MESSAGE)
+}
+
+/// Options for picking an adapter
+#[derive(Debug, Clone, Copy)]
+pub struct GpuContextOptions {
+    pub backends: Backends,
+    pub power_preference: PowerPreference,
+    /// Try the fallback (software) adapter first, and only use a hardware
+    /// adapter if there is no fallback adapter.
+    pub prefer_fallback_adapter: bool,
+}
+
+impl GpuContextOptions {
+    /// `prefer_fallback_adapter` is used if the config doesn't say whether to
+    /// prefer the fallback adapter.
+    pub fn from_config(config: &BenchConfig, prefer_fallback_adapter: bool) -> Self {
+        Self {
+            backends: config.backends,
+            power_preference: config.power_preference,
+            prefer_fallback_adapter: config.prefer_fallback_adapter.unwrap_or(prefer_fallback_adapter),
+        }
+    }
+}
+
+/// Everything needed to talk to the GPU. Surfaces, offscreen targets, and
+/// compute work all borrow the device and queue from here, so several of them
+/// can share one device.
+pub struct GpuContext {
+    pub instance: Instance,
+    pub adapter: Adapter,
+    pub device: Device,
+    pub queue: Queue,
+    pub adapter_info: AdapterInfo,
+    /// Set by the device's error handler
+    device_lost: Arc<AtomicBool>,
+}
+
+pub fn create_instance(backends: Backends) -> Instance {
+    Instance::new(InstanceDescriptor {
+        backends,
+        dx12_shader_compiler: Default::default(),
+    })
+}
+
+/// Get information about every adapter for the given backends
+#[cfg(not(target_family = "wasm"))]
+pub fn list_adapters(backends: Backends) -> Vec<AdapterInfo> {
+    create_instance(backends)
+        .enumerate_adapters(backends)
+        .map(|adapter| adapter.get_info())
+        .collect()
+}
+
+/// Log every error that the device doesn't otherwise report, and set
+/// `device_lost` when one of them says the device has been lost. Any other
+/// error is fatal, like it is without a handler.
+fn handle_errors(device: &Device) -> Arc<AtomicBool> {
+    let device_lost = Arc::new(AtomicBool::new(false));
+    let lost = Arc::clone(&device_lost);
+    device.on_uncaptured_error(Box::new(move |error| {
+        let message = error.to_string();
+        if !is_device_lost_error(&message) {
+            panic!("wgpu error: {message}");
+        }
+        // Everything that uses the device fails after it's lost, so only
+        // the first error is worth showing
+        if !lost.swap(true, Ordering::Relaxed) {
+            platform::log(&format!("The device was lost: {message}"));
+        }
+    }));
+    device_lost
+}
+
+/// Find an adapter that suits the options, and open a device on it
+async fn request_device(
+    instance: &Instance,
+    options: &GpuContextOptions,
+    compatible_surface: Option<&Surface>,
+) -> Result<(Adapter, Device, Queue), Box<dyn Error>> {
+    let fallback_order: &[bool] = if options.prefer_fallback_adapter {
+        &[true, false]
+    } else {
+        &[false]
+    };
+    let mut adapter = None;
+    for &force_fallback_adapter in fallback_order {
+        adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: options.power_preference,
+                force_fallback_adapter,
+                compatible_surface,
+            })
+            .await;
+        if adapter.is_some() {
+            break;
+        }
+    }
+    let adapter = adapter.ok_or(String::from("No suitable GPU found"))?;
+    // These features are nice to have, but we can do without them.
+    // Block-compressed textures get decompressed if they're not
+    // supported.
+    let features = adapter.features() & (
+        Features::TIMESTAMP_QUERY |
+        Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES |
+        Features::TEXTURE_COMPRESSION_BC |
+        Features::TEXTURE_COMPRESSION_ETC2 |
+        Features::TEXTURE_COMPRESSION_ASTC
+    );
+    // Ask for compute shaders and storage buffers if the adapter has
+    // them, and stick to what WebGL2 can do otherwise.
+    let compute = adapter.get_downlevel_capabilities().flags.contains(DownlevelFlags::COMPUTE_SHADERS);
+    let limits = if compute && Limits::downlevel_defaults().check_limits(&adapter.limits()) {
+        Limits::downlevel_defaults()
+    } else {
+        Limits::downlevel_webgl2_defaults()
+    };
+    let (device, queue) = adapter
+        .request_device(
+            &DeviceDescriptor {
+                label: Some("My GPU"),
+                features,
+                limits,
+            },
+            None,
+        )
+        .await?;
+    Ok((adapter, device, queue))
+}
+
+impl GpuContext {
+    /// Set up the adapter, device, and queue. If the context is going to be
+    /// used for rendering to a window, `compatible_surface` should be a
+    /// surface created from `instance`, so that the adapter can present to
+    /// it.
+    pub async fn new(
+        instance: Instance,
+        options: &GpuContextOptions,
+        compatible_surface: Option<&Surface>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (adapter, device, queue) = request_device(&instance, options, compatible_surface).await?;
+        let device_lost = handle_errors(&device);
+        Ok(Self {
+            instance,
+            adapter_info: adapter.get_info(),
+            adapter,
+            device,
+            queue,
+            device_lost,
+        })
+    }
+    /// Get a new adapter and device from the same instance, after the device
+    /// has been lost. Everything made with the old device has to be made
+    /// again.
+    pub async fn recreate(
+        &mut self,
+        options: &GpuContextOptions,
+        compatible_surface: Option<&Surface>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (adapter, device, queue) = request_device(&self.instance, options, compatible_surface).await?;
+        self.device_lost = handle_errors(&device);
+        self.adapter_info = adapter.get_info();
+        self.adapter = adapter;
+        self.device = device;
+        self.queue = queue;
+        Ok(())
+    }
+    /// Whether the device has been lost, going by the errors it's reported
+    pub fn device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+    /// For when the device turns out to be lost some other way, like a panic
+    /// from wgpu
+    pub fn set_device_lost(&self) {
+        self.device_lost.store(true, Ordering::Relaxed);
+    }
+    /// Whether the sample count can be used to render into the given colour
+    /// format along with a depth buffer
+    pub fn sample_count_supported(&self, format: TextureFormat, count: u32) -> bool {
+        let device_features = self.device.features();
+        // Without adapter specific format features, the device only allows
+        // the sample counts that every adapter supports.
+        let format_features = |format: TextureFormat| {
+            if device_features.contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+                self.adapter.get_texture_format_features(format)
+            } else {
+                format.guaranteed_format_features(device_features)
+            }
+        };
+        [format, DEPTH_FORMAT].into_iter()
+            .all(|format| format_features(format).flags.sample_count_supported(count))
+    }
+    /// The largest sample count, no larger than `requested`, that can be used
+    /// to render into the given colour format along with a depth buffer.
+    pub fn supported_sample_count(&self, format: TextureFormat, requested: u32) -> u32 {
+        let sample_count = SAMPLE_COUNTS.into_iter()
+            .filter(|&count| count <= requested)
+            .find(|&count| self.sample_count_supported(format, count))
+            .unwrap_or(1);
+        if sample_count != requested {
+            platform::log(&format!(
+                "{requested}x MSAA is not supported for {format:?}, so {sample_count}x will be used instead"
+            ));
+        }
+        sample_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::core::device::{queue::QueueSubmitError, DeviceError};
+
+    use super::*;
+
+    #[test]
+    fn device_lost_messages() {
+        // The errors wgpu-core gives when the device is lost, and the way
+        // wgpu formats them when it panics in Queue::submit
+        let lost = DeviceError::Lost.to_string();
+        assert!(is_device_lost_error(&lost));
+        let submit = QueueSubmitError::Queue(DeviceError::Lost);
+        let panic = format!("Error in Queue::submit: Validation Error\n\nCaused by:\n    {submit}\n");
+        assert!(is_device_lost_error(&panic));
+        assert!(!is_device_lost_error(&DeviceError::Invalid.to_string()));
+        assert!(!is_device_lost_error(&DeviceError::OutOfMemory.to_string()));
+    }
+}
@@ -0,0 +1,240 @@
+use std::{borrow::Cow, cell::{Ref, RefCell}, error::Error, fmt, mem, num::NonZeroU64};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::*;
+
+use crate::platform;
+
+/// How the smaller mip levels of a texture are made
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MipGeneration {
+    /// Resize the image on the CPU for each level, and upload every level
+    Cpu,
+    /// Upload the full size image, and draw each level from the one above it
+    #[default]
+    Gpu,
+}
+
+impl fmt::Display for MipGeneration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MipGeneration::Cpu => "cpu",
+            MipGeneration::Gpu => "gpu",
+        })
+    }
+}
+
+/// Which layer of the source texture to read, and whether it's
+/// premultiplied. Laid out like `MipParams` in mipmap.wgsl.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct MipParams {
+    layer: u32,
+    premultiplied: u32,
+    _padding: [u32; 2],
+}
+
+/// Fills in the mip chain of a texture with a render pass for each level of
+/// each layer, which averages 2x2 blocks of the level above it. The same
+/// filter as the CPU uses, in linear space, with premultiplied alpha.
+pub struct MipGenerator {
+    shader_module: ShaderModule,
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    /// One pipeline for each texture format it's been used on so far
+    pipelines: RefCell<Vec<(TextureFormat, RenderPipeline)>>,
+}
+
+impl MipGenerator {
+    pub async fn new(device: &Device) -> Result<Self, Box<dyn Error>> {
+        let shader_code = Cow::from(platform::read_text_asset("assets/mipmap.wgsl").await?);
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Mipmap shader module"),
+            source: ShaderSource::Wgsl(shader_code),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Mipmap bindings (layout)"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: NonZeroU64::new(mem::size_of::<MipParams>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Mipmap pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        Ok(Self {
+            shader_module,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines: RefCell::new(Vec::new()),
+        })
+    }
+    /// Whether textures of the given format can have their mips drawn
+    pub fn supports(device: &Device, format: TextureFormat) -> bool {
+        format.guaranteed_format_features(device.features())
+            .allowed_usages
+            .contains(TextureUsages::RENDER_ATTACHMENT)
+    }
+    /// The pipeline that draws into textures of the given format, made the
+    /// first time it's needed
+    fn pipeline(&self, device: &Device, format: TextureFormat) -> Ref<'_, RenderPipeline> {
+        if !self.pipelines.borrow().iter().any(|&(pipeline_format, _)| pipeline_format == format) {
+            let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("Mipmap pipeline"),
+                layout: Some(&self.pipeline_layout),
+                vertex: VertexState {
+                    module: &self.shader_module,
+                    entry_point: "vertex_main",
+                    buffers: &[],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                fragment: Some(FragmentState {
+                    module: &self.shader_module,
+                    entry_point: "pixel_main",
+                    targets: &[Some(format.into())],
+                }),
+                multiview: None,
+            });
+            self.pipelines.borrow_mut().push((format, pipeline));
+        }
+        Ref::map(self.pipelines.borrow(), |pipelines| {
+            pipelines.iter()
+                .find(|&&(pipeline_format, _)| pipeline_format == format)
+                .map(|(_, pipeline)| pipeline)
+                .expect("The pipeline was just made")
+        })
+    }
+    /// Draw every mip level after the first, on every layer. The texture has
+    /// to be a 2D array texture with `TEXTURE_BINDING` and `COPY_DST` usage,
+    /// and the first mip level has to be filled in already. `premultiplied`
+    /// says whether the colours in the texture are premultiplied already.
+    pub fn generate(&self, device: &Device, queue: &Queue, texture: &Texture, premultiplied: bool) {
+        let format = texture.format();
+        let mip_level_count = texture.mip_level_count();
+        let layer_count = texture.depth_or_array_layers();
+        let pipeline = self.pipeline(device, format);
+        // One set of parameters for each layer, at offsets that can be used
+        // as dynamic offsets
+        let params_stride = (mem::size_of::<MipParams>() as u32)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment);
+        let params_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Mipmap parameters"),
+            size: (params_stride * layer_count) as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (0..layer_count).for_each(|layer| {
+            let params = MipParams {
+                layer,
+                premultiplied: premultiplied as u32,
+                _padding: [0; 2],
+            };
+            queue.write_buffer(&params_buffer, (params_stride * layer) as BufferAddress, bytemuck::bytes_of(&params));
+        });
+        let mut commands = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Mipmap commands"),
+        });
+        // GL can't sample from any mip level but the first of a texture, so
+        // each level is drawn into a texture of its own, copied into place,
+        // and then used as the source for the next level.
+        let mut source = texture.create_view(&TextureViewDescriptor {
+            label: Some("Mipmap source"),
+            dimension: Some(TextureViewDimension::D2Array),
+            mip_level_count: Some(1),
+            ..Default::default()
+        });
+        for mip_level in 1..mip_level_count {
+            let size = texture.size().mip_level_size(mip_level, texture.dimension());
+            let level = device.create_texture(&TextureDescriptor {
+                label: Some("Mipmap level"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Mipmap bindings"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&source),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Buffer(BufferBinding {
+                            buffer: &params_buffer,
+                            offset: 0,
+                            size: NonZeroU64::new(mem::size_of::<MipParams>() as u64),
+                        }),
+                    },
+                ],
+            });
+            for layer in 0..layer_count {
+                let target = level.create_view(&TextureViewDescriptor {
+                    label: Some("Mipmap target"),
+                    dimension: Some(TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+                let mut pass = commands.begin_render_pass(&RenderPassDescriptor {
+                    label: Some("Mipmap pass"),
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: &target,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Clear(Color::TRANSPARENT),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                pass.set_pipeline(&pipeline);
+                pass.set_bind_group(0, &bind_group, &[params_stride * layer]);
+                pass.draw(0..3, 0..1);
+            }
+            commands.copy_texture_to_texture(
+                level.as_image_copy(),
+                ImageCopyTexture {
+                    texture,
+                    mip_level,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                size,
+            );
+            source = level.create_view(&TextureViewDescriptor {
+                label: Some("Mipmap source"),
+                dimension: Some(TextureViewDimension::D2Array),
+                ..Default::default()
+            });
+        }
+        queue.submit([commands.finish()]);
+    }
+}
@@ -1,2 +1,9 @@
+pub mod atlas;
+pub mod compressed;
+pub mod context;
+pub mod mipmap;
+pub mod screenshot;
 pub mod texture;
 pub mod surface;
+#[cfg(not(target_family = "wasm"))]
+pub mod offscreen;
@@ -0,0 +1,68 @@
+use wgpu::*;
+
+use super::{
+    context::GpuContext,
+    texture::{depth_texture, msaa_texture_view, RenderTarget, SimpleTextureView},
+};
+
+/// A colour texture and a depth texture to render into when there is no
+/// window to present to.
+pub struct OffscreenTarget {
+    pub color_texture: Texture,
+    pub color_texture_view: TextureView,
+    pub depth_texture_view: TextureView,
+    sample_count: u32,
+    msaa_view: Option<TextureView>,
+}
+
+/// There is no surface to ask for a preferred format, so use the format most
+/// native surfaces prefer.
+const OFFSCREEN_FORMAT: TextureFormat = TextureFormat::Bgra8UnormSrgb;
+
+impl OffscreenTarget {
+    /// If `srgb` is false, the target doesn't gamma-encode its output, like a
+    /// surface viewed without its sRGB suffix. `sample_count` is lowered if
+    /// the format doesn't support it.
+    pub fn new(context: &GpuContext, width: u32, height: u32, srgb: bool, sample_count: u32) -> Self {
+        let device = &context.device;
+        let format = if srgb { OFFSCREEN_FORMAT } else { OFFSCREEN_FORMAT.remove_srgb_suffix() };
+        let color_texture = device.create_texture(&TextureDescriptor {
+            label: Some("Offscreen colour texture"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[format],
+        });
+        let color_texture_view = SimpleTextureView::new(&color_texture, Some("Offscreen colour view"));
+        let sample_count = context.supported_sample_count(format, sample_count);
+        // The view keeps the depth texture alive
+        let (_, depth_texture_view) = depth_texture(device, width, height, sample_count);
+        let msaa_view = msaa_texture_view(device, format, width, height, sample_count);
+        Self {
+            color_texture,
+            color_texture_view,
+            depth_texture_view,
+            sample_count,
+            msaa_view,
+        }
+    }
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+    pub fn render_target(&self) -> RenderTarget<'_> {
+        RenderTarget::new(&self.color_texture_view, self.msaa_view.as_ref(), &self.depth_texture_view)
+    }
+    pub fn format(&self) -> TextureFormat {
+        self.color_texture.format()
+    }
+    pub fn size(&self) -> (u32, u32) {
+        (self.color_texture.width(), self.color_texture.height())
+    }
+}
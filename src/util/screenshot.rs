@@ -0,0 +1,90 @@
+use std::{error::Error, io::Cursor};
+
+use image::{ImageOutputFormat, RgbaImage};
+use wgpu::*;
+
+/// A copy of a frame on its way back from the GPU
+pub struct Screenshot {
+    buffer: Buffer,
+    width: u32,
+    height: u32,
+    /// Rows in the buffer are padded out to `COPY_BYTES_PER_ROW_ALIGNMENT`
+    padded_bytes_per_row: u32,
+    /// Whether the pixels are BGRA rather than RGBA
+    bgra: bool,
+    /// The frame that was copied
+    frame: u32,
+}
+
+impl Screenshot {
+    /// Record a copy of a texture into a buffer that can be read back. The
+    /// texture needs `COPY_SRC` usage, and an 8-bit RGBA or BGRA format.
+    /// `frame` is the number of the frame being copied.
+    pub fn copy(
+        device: &Device,
+        commands: &mut CommandEncoder,
+        texture: &Texture,
+        frame: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let bgra = match texture.format() {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            format => return Err(Box::from(format!("Screenshots of {format:?} textures aren't supported"))),
+        };
+        let (width, height) = (texture.width(), texture.height());
+        let padded_bytes_per_row = (width * 4).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Screenshot buffer"),
+            size: padded_bytes_per_row as BufferAddress * height as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        commands.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        Ok(Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+            bgra,
+            frame,
+        })
+    }
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+    /// Start reading the buffer back, once the copy has been submitted.
+    /// `on_mapped` is called when the device is polled after that's done.
+    pub fn map(&self, on_mapped: impl FnOnce(Result<(), BufferAsyncError>) + WasmNotSend + 'static) {
+        self.buffer.slice(..).map_async(MapMode::Read, on_mapped);
+    }
+    /// Encode the pixels as a PNG file, once the buffer has been mapped
+    pub fn to_png(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let row_size = self.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row_size * self.height as usize);
+        {
+            let view = self.buffer.slice(..).get_mapped_range();
+            view.chunks_exact(self.padded_bytes_per_row as usize)
+                .for_each(|row| pixels.extend_from_slice(&row[..row_size]));
+        }
+        self.buffer.unmap();
+        if self.bgra {
+            pixels.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+        let image = RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or("The screenshot is the wrong size")?;
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+        Ok(png)
+    }
+}
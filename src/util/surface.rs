@@ -1,135 +1,244 @@
 use std::{error::Error, ops::Deref};
 use wgpu::*;
-use winit::{dpi::PhysicalSize, window::Window};
+use winit::dpi::PhysicalSize;
+
+use crate::platform;
+
+use super::{
+    context::{GpuContext, SAMPLE_COUNTS},
+    texture::{depth_texture, msaa_texture_view, RenderTarget},
+};
+
+/// The present modes that can be switched between at runtime, in the order
+/// they're switched through
+const SWITCHABLE_PRESENT_MODES: [PresentMode; 4] = [
+    PresentMode::Fifo,
+    PresentMode::FifoRelaxed,
+    PresentMode::Immediate,
+    PresentMode::Mailbox,
+];
 
 pub struct SurfaceInfo {
     pub surface: Surface,
-    pub backend: Backend,
+    config: SurfaceConfiguration,
+    /// What the surface supports, according to its capabilities
+    present_modes: Vec<PresentMode>,
+    /// The format that the surface textures are viewed as. This is the
+    /// surface format without the sRGB suffix if linear output was asked for.
+    view_format: TextureFormat,
     pub depth_texture: Texture,
     pub depth_texture_view: TextureView,
+    sample_count: u32,
+    /// Rendered into instead of the surface texture, and resolved into it, if
+    /// `sample_count` is more than 1
+    msaa_view: Option<TextureView>,
+}
+
+/// Prefer an sRGB format, so that blending happens in linear space and the
+/// output is gamma-encoded by the hardware.
+fn choose_format(supported: &[TextureFormat]) -> Option<TextureFormat> {
+    supported.iter()
+        .copied()
+        .find(TextureFormat::is_srgb)
+        .or_else(|| supported.first().copied())
 }
 
-fn surface_texture_format() -> TextureFormat {
-    #[cfg(target_family="wasm")]
-    let format = TextureFormat::Rgba8UnormSrgb;
-    #[cfg(not(target_family="wasm"))]
-    let format = TextureFormat::Bgra8UnormSrgb;
-    format
+/// The benchmark doesn't need a transparent window, so prefer opaque.
+fn choose_alpha_mode(supported: &[CompositeAlphaMode]) -> CompositeAlphaMode {
+    [CompositeAlphaMode::Opaque, CompositeAlphaMode::Inherit, CompositeAlphaMode::Auto]
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .or_else(|| supported.first().copied())
+        .unwrap_or(CompositeAlphaMode::Auto)
 }
 
-fn surface_config_with_dims(width: u32, height: u32) -> SurfaceConfiguration {
-    let format = surface_texture_format();
-    SurfaceConfiguration {
-        usage: TextureUsages::RENDER_ATTACHMENT,
-        format,
-        width,
-        height,
-        present_mode: PresentMode::Fifo,
-        alpha_mode: CompositeAlphaMode::Opaque,
-        view_formats: vec![format],
+/// Turn the requested present mode into one the surface supports. The
+/// automatic modes are resolved here, rather than by wgpu, so that the mode
+/// that actually gets used can be reported.
+fn choose_present_mode(requested: PresentMode, supported: &[PresentMode]) -> PresentMode {
+    let first_supported = |modes: &[PresentMode]| modes.iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo);
+    match requested {
+        PresentMode::AutoVsync => first_supported(&[PresentMode::FifoRelaxed, PresentMode::Fifo]),
+        PresentMode::AutoNoVsync => first_supported(&[PresentMode::Immediate, PresentMode::Mailbox, PresentMode::Fifo]),
+        mode if supported.contains(&mode) => mode,
+        // Every surface supports Fifo
+        _ => PresentMode::Fifo,
     }
 }
 
 impl SurfaceInfo {
-    pub async fn create(
-        instance: &Instance,
-        window: &Window,
-    ) -> Result<(Self, Device, Queue), Box<dyn Error>> {
-        // In order for the adapter to be able to render to the surface, the
-        // adapter needs a surface to be compatible with.
-        let surface = unsafe { instance.create_surface(window) }?;
-        let PhysicalSize { width, height } = window.inner_size();
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .ok_or(String::from("No suitable GPU found"))?;
-        // I like to show the user which backend is being used once they start
-        // the app.
-        let backend = adapter.get_info().backend;
-        let (device, queue) = adapter
-            .request_device(
-                &DeviceDescriptor {
-                    label: Some("My GPU"),
-                    features: Features::empty(),
-                    limits: Limits::downlevel_webgl2_defaults(),
-                },
-                None,
-            )
-            .await?;
-        // wasm and native support different surface texture formats
-        let config = surface_config_with_dims(width, height);
-        surface.configure(&device, &config);
-        // The depth texture is the same size as the surface
-        let depth_texture = device.create_texture(&TextureDescriptor {
-            label: Some("My depth texture"),
-            size: Extent3d {
-                width,
-                height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Depth32Float,
-            usage: TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[TextureFormat::Depth32Float],
-        });
-        let depth_texture_view = depth_texture.create_view(&TextureViewDescriptor {
-            label: Some("View for my depth texture"),
-            format: Some(TextureFormat::Depth32Float),
-            dimension: Some(TextureViewDimension::D2),
-            aspect: TextureAspect::DepthOnly,
-            base_mip_level: 0,
-            mip_level_count: None,
-            base_array_layer: 0,
-            array_layer_count: None,
-        });
-        Ok((
-            Self {
-                surface,
-                backend,
-                depth_texture,
-                depth_texture_view,
-            },
-            device,
-            queue,
-        ))
+    /// Attach a surface to an existing GPU context, and configure it for the
+    /// given size. The format and alpha mode are negotiated from the
+    /// surface's capabilities. If `srgb` is false, and the surface format is
+    /// sRGB, the surface textures are viewed without the sRGB encoding.
+    /// `sample_count` is lowered if the format doesn't support it.
+    pub fn new(
+        context: &GpuContext,
+        surface: Surface,
+        size: PhysicalSize<u32>,
+        present_mode: PresentMode,
+        srgb: bool,
+        sample_count: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        if !context.adapter.is_surface_supported(&surface) {
+            return Err(Box::from(format!(
+                "{} can't present to this surface",
+                context.adapter_info.name
+            )));
+        }
+        let PhysicalSize { width, height } = size;
+        let capabilities = surface.get_capabilities(&context.adapter);
+        let present_modes = capabilities.present_modes;
+        let chosen_mode = choose_present_mode(present_mode, &present_modes);
+        if chosen_mode != present_mode && !matches!(present_mode, PresentMode::AutoVsync | PresentMode::AutoNoVsync) {
+            platform::log(&format!("{present_mode:?} is not supported, so {chosen_mode:?} will be used instead"));
+        }
+        // An sRGB format if the surface has one, so that the view can be made
+        // linear below if that was asked for
+        let format = choose_format(&capabilities.formats)
+            .ok_or("The surface doesn't support any texture formats")?;
+        let mut view_format = format;
+        if !srgb && format.is_srgb() {
+            let surface_view_formats = context.adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(DownlevelFlags::SURFACE_VIEW_FORMATS);
+            if surface_view_formats {
+                view_format = format.remove_srgb_suffix();
+            } else {
+                platform::log("This adapter can't view the surface in a different format, so the output will be sRGB");
+            }
+        }
+        // Screenshots are copied out of the surface texture, if it allows it
+        let usage = TextureUsages::RENDER_ATTACHMENT | (capabilities.usages & TextureUsages::COPY_SRC);
+        let config = SurfaceConfiguration {
+            usage,
+            format,
+            width,
+            height,
+            present_mode: chosen_mode,
+            alpha_mode: choose_alpha_mode(&capabilities.alpha_modes),
+            view_formats: vec![view_format],
+        };
+        // The depth texture is the same size as the surface. If the window
+        // starts out minimized, it's made at 1x1 until the window is resized.
+        let sample_count = context.supported_sample_count(view_format, sample_count);
+        let (texture_width, texture_height) = (width.max(1), height.max(1));
+        let (depth_texture, depth_texture_view) = depth_texture(&context.device, texture_width, texture_height, sample_count);
+        let msaa_view = msaa_texture_view(&context.device, view_format, texture_width, texture_height, sample_count);
+        let surface_info = Self {
+            surface,
+            config,
+            present_modes,
+            view_format,
+            depth_texture,
+            depth_texture_view,
+            sample_count,
+            msaa_view,
+        };
+        surface_info.reconfigure(&context.device);
+        Ok(surface_info)
     }
+    /// Configure the surface for a new window size. A minimized window can be
+    /// 0x0, which a surface can't be configured for, so the surface is left
+    /// alone until the window has a size again.
     pub fn resize(&mut self, device: &Device, new_size: PhysicalSize<u32>) {
         let PhysicalSize { width, height } = new_size;
-        let config = surface_config_with_dims(width, height);
-        self.surface.configure(device, &config);
-        self.depth_texture = device.create_texture(&TextureDescriptor {
-            label: Some("My depth texture"),
-            size: Extent3d {
-                width,
-                height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Depth32Float,
-            usage: TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[TextureFormat::Depth32Float],
-        });
-        self.depth_texture_view = self.depth_texture.create_view(&TextureViewDescriptor {
-            label: Some("View for my depth texture"),
-            format: Some(TextureFormat::Depth32Float),
-            dimension: Some(TextureViewDimension::D2),
-            aspect: TextureAspect::DepthOnly,
-            base_mip_level: 0,
-            mip_level_count: None,
-            base_array_layer: 0,
-            array_layer_count: None,
-        });
+        self.config.width = width;
+        self.config.height = height;
+        if self.is_zero_sized() {
+            return;
+        }
+        self.surface.configure(device, &self.config);
+        (self.depth_texture, self.depth_texture_view) = depth_texture(device, width, height, self.sample_count);
+        self.msaa_view = msaa_texture_view(device, self.view_format, width, height, self.sample_count);
+    }
+    /// Configure the surface for a new device, after the old one has been
+    /// lost, and make the depth and multisampled textures again. The present
+    /// mode and sample count are checked again, in case the new adapter is a
+    /// different one.
+    pub fn recreate(&mut self, context: &GpuContext) {
+        let device = &context.device;
+        self.present_modes = self.surface.get_capabilities(&context.adapter).present_modes;
+        self.config.present_mode = choose_present_mode(self.config.present_mode, &self.present_modes);
+        self.sample_count = context.supported_sample_count(self.view_format, self.sample_count);
+        let (width, height) = (self.config.width.max(1), self.config.height.max(1));
+        (self.depth_texture, self.depth_texture_view) = depth_texture(device, width, height, self.sample_count);
+        self.msaa_view = msaa_texture_view(device, self.view_format, width, height, self.sample_count);
+        self.reconfigure(device);
+    }
+    /// Configure the surface with the current settings, like after it's been
+    /// lost or gone out of date. Nothing happens while the window has no area.
+    pub fn reconfigure(&self, device: &Device) {
+        if !self.is_zero_sized() {
+            self.surface.configure(device, &self.config);
+        }
+    }
+    /// Whether the surface textures can be copied from, for screenshots
+    pub fn can_copy(&self) -> bool {
+        self.config.usage.contains(TextureUsages::COPY_SRC)
     }
+    /// Whether the window has no area, so there's nothing to render to
+    pub fn is_zero_sized(&self) -> bool {
+        self.config.width == 0 || self.config.height == 0
+    }
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+    /// Switch to the next larger sample count that's supported, or back to 1
+    /// after the largest. Returns the new sample count. Anything that renders
+    /// into the surface has to be made again to match.
+    pub fn next_sample_count(&mut self, context: &GpuContext) -> u32 {
+        self.sample_count = SAMPLE_COUNTS.into_iter()
+            .rev()
+            .filter(|&count| count > self.sample_count)
+            .find(|&count| context.sample_count_supported(self.view_format, count))
+            .unwrap_or(1);
+        let (width, height) = (self.config.width.max(1), self.config.height.max(1));
+        (self.depth_texture, self.depth_texture_view) = depth_texture(&context.device, width, height, self.sample_count);
+        self.msaa_view = msaa_texture_view(&context.device, self.view_format, width, height, self.sample_count);
+        self.sample_count
+    }
+    /// The attachments to render into, given a view of the current surface
+    /// texture
+    pub fn render_target<'a>(&'a self, surface_view: &'a TextureView) -> RenderTarget<'a> {
+        RenderTarget::new(surface_view, self.msaa_view.as_ref(), &self.depth_texture_view)
+    }
+    /// The format to render into, which is what `view` creates views as
     pub fn format(&self) -> TextureFormat {
-        surface_texture_format()
+        self.view_format
+    }
+    /// Create a view of a surface texture to render into
+    pub fn view(&self, texture: &SurfaceTexture) -> TextureView {
+        texture.texture.create_view(&TextureViewDescriptor {
+            label: Some("Surface view"),
+            format: Some(self.view_format),
+            ..Default::default()
+        })
+    }
+    pub fn present_mode(&self) -> PresentMode {
+        self.config.present_mode
+    }
+    /// Reconfigure the surface with a different present mode, falling back to
+    /// a supported one if necessary. Returns the present mode that's used.
+    pub fn set_present_mode(&mut self, device: &Device, present_mode: PresentMode) -> PresentMode {
+        self.config.present_mode = choose_present_mode(present_mode, &self.present_modes);
+        self.reconfigure(device);
+        self.config.present_mode
+    }
+    /// Switch to the next supported present mode. Returns the new present
+    /// mode.
+    pub fn next_present_mode(&mut self, device: &Device) -> PresentMode {
+        let supported: Vec<PresentMode> = SWITCHABLE_PRESENT_MODES.into_iter()
+            .filter(|mode| self.present_modes.contains(mode))
+            .collect();
+        let next = supported.iter()
+            .position(|&mode| mode == self.config.present_mode)
+            .map_or(0, |index| (index + 1) % supported.len());
+        self.set_present_mode(device, supported.get(next).copied().unwrap_or(PresentMode::Fifo))
     }
 }
 
@@ -1,10 +1,17 @@
-use image::{DynamicImage, imageops::FilterType, ImageBuffer, Rgba};
+use image::{DynamicImage, imageops::FilterType, ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
 use wgpu::{TextureDescriptor, Extent3d, TextureFormat, TextureUsages, ImageCopyTexture, Origin3d, TextureAspect, ImageDataLayout};
-use std::{error::Error, borrow::{Cow, Borrow}};
+use std::error::Error;
 use crate::platform;
 
+use super::{
+    compressed::{self, CompressedImage, LoadedImage},
+    context::GpuContext,
+    mipmap::MipGenerator,
+};
+
 pub struct SimpleTextureView;
 impl SimpleTextureView {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(texture: &wgpu::Texture, label: Option<&'static str>) -> wgpu::TextureView {
         let format = texture.format();
         let dimension = match texture.dimension() {
@@ -25,19 +32,35 @@ impl SimpleTextureView {
     }
 }
 
-pub const MIP_LEVELS: u32 = 4;
+/// How many mip levels a texture of the given size has, all the way down to
+/// 1x1
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+/// The size of a mip level, which is never less than 1x1
+fn mip_size(width: u32, height: u32, level: u32) -> (u32, u32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+/// How many layers a texture array needs for `layer_count` images. The GL
+/// backend only makes an array texture if there's more than one layer, and an
+/// ordinary 2D texture can't be viewed as an array, so on GL there's an unused
+/// layer if there's only one image.
+fn array_layers(context: &GpuContext, layer_count: u32) -> u32 {
+    match context.adapter_info.backend {
+        wgpu::Backend::Gl => layer_count.max(2),
+        _ => layer_count,
+    }
+}
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub sampler: wgpu::Sampler,
     pub view: wgpu::TextureView,
-    format: TextureFormat,
-}
-
-macro_rules! conversion {
-    ($conversion_function: path) => {
-        &|i| DynamicImage::from($conversion_function(i))
-    };
+    /// How many images are in the texture, which isn't always how many layers
+    /// it has
+    layer_count: u32,
 }
 
 trait IntoRgba16Float {
@@ -57,43 +80,249 @@ impl IntoRgba16Float for DynamicImage {
     }
 }
 
-impl Texture {
-    pub async fn load_asset(device: &wgpu::Device, queue: &wgpu::Queue, path: &'static str, label: Option<&str>) -> Result<Self, Box<dyn Error>> {
-        let asset_data = platform::read_asset(path).await?;
-        let image = image::load_from_memory(&asset_data)?;
-        Texture::from_image(device, queue, image, label)
+/// The texture format that an image should be uploaded as.
+/// For now, the image has to be Rgba8UnormSrgb or Rgba16Float.
+/// wgpu doesn't implement float32-filterable.
+/// The `half` crate is used to convert images to Rgba16Float.
+fn texture_format(image: &DynamicImage) -> Result<TextureFormat, String> {
+    match image {
+        DynamicImage::ImageLuma8(_) |
+        DynamicImage::ImageLumaA8(_) |
+        DynamicImage::ImageRgb8(_) |
+        DynamicImage::ImageRgba8(_) => Ok(TextureFormat::Rgba8UnormSrgb),
+        DynamicImage::ImageLuma16(_) |
+        DynamicImage::ImageLumaA16(_) |
+        DynamicImage::ImageRgb16(_) |
+        DynamicImage::ImageRgba16(_) |
+        DynamicImage::ImageRgb32F(_) |
+        DynamicImage::ImageRgba32F(_) => Ok(TextureFormat::Rgba16Float),
+        f => Err(format!("Unknown/unsupported format {f:?}")),
     }
-    pub fn from_image(device: &wgpu::Device, queue: &wgpu::Queue, image: DynamicImage, label: Option<&str>) -> Result<Self, Box<dyn Error>> {
-        let width = image.width();
-        let height = image.height();
-        // For now, the image has to be Rgba8UnormSrgb or Rgba16Float.
-        // wgpu doesn't implement float32-filterable.
-        // The `half` crate is used to convert images to Rgba16Float.
-        let (format, conversion): (Result<TextureFormat, String>, Option<&dyn Fn(DynamicImage) -> DynamicImage>) = match &image {
-            DynamicImage::ImageLuma8(_) => (Ok(TextureFormat::Rgba8UnormSrgb), Some(conversion!(DynamicImage::into_rgba8))),
-            DynamicImage::ImageLumaA8(_) => (Ok(TextureFormat::Rgba8UnormSrgb), Some(conversion!(DynamicImage::into_rgba8))),
-            DynamicImage::ImageRgb8(_) => (Ok(TextureFormat::Rgba8UnormSrgb), Some(conversion!(DynamicImage::into_rgba8))),
-            DynamicImage::ImageRgba8(_) => (Ok(TextureFormat::Rgba8UnormSrgb), None),
-            DynamicImage::ImageLuma16(_) => (Ok(TextureFormat::Rgba16Float), Some(conversion!(DynamicImage::into_rgba16f))),
-            DynamicImage::ImageLumaA16(_) => (Ok(TextureFormat::Rgba16Float), Some(conversion!(DynamicImage::into_rgba16f))),
-            DynamicImage::ImageRgb16(_) => (Ok(TextureFormat::Rgba16Float), Some(conversion!(DynamicImage::into_rgba16f))),
-            DynamicImage::ImageRgba16(_) => (Ok(TextureFormat::Rgba16Float), Some(conversion!(DynamicImage::into_rgba16f))),
-            DynamicImage::ImageRgb32F(_) => (Ok(TextureFormat::Rgba16Float), Some(conversion!(DynamicImage::into_rgba16f))),
-            DynamicImage::ImageRgba32F(_) => (Ok(TextureFormat::Rgba16Float), Some(conversion!(DynamicImage::into_rgba16f))),
-            f => (Err(format!("Unknown/unsupported format {f:?}")), None),
+}
+
+/// Convert an image to the layout of the given texture format
+fn convert(image: DynamicImage, format: TextureFormat) -> DynamicImage {
+    match format {
+        TextureFormat::Rgba16Float => DynamicImage::from(DynamicImage::into_rgba16f(image)),
+        _ => DynamicImage::from(image.into_rgba8()),
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(super) fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// An image that's been converted, as linear colours with premultiplied
+/// alpha. If it's already premultiplied, it's left as it is.
+fn to_linear_premultiplied(image: &DynamicImage, premultiplied: bool) -> Rgba32FImage {
+    let mut linear = match image {
+        DynamicImage::ImageRgba8(buffer) => Rgba32FImage::from_fn(buffer.width(), buffer.height(), |x, y| {
+            let Rgba([r, g, b, a]) = *buffer.get_pixel(x, y);
+            let channel = |value: u8| srgb_to_linear(value as f32 / 255.);
+            Rgba([channel(r), channel(g), channel(b), a as f32 / 255.])
+        }),
+        // Float textures aren't decoded when they're sampled, so they're
+        // already linear as far as the GPU is concerned
+        DynamicImage::ImageRgba16(buffer) => Rgba32FImage::from_fn(buffer.width(), buffer.height(), |x, y| {
+            Rgba(buffer.get_pixel(x, y).0.map(|channel| half::f16::from_bits(channel).to_f32()))
+        }),
+        image => image.to_rgba32f(),
+    };
+    if !premultiplied {
+        linear.pixels_mut().for_each(|Rgba(pixel)| {
+            let alpha = pixel[3];
+            pixel[..3].iter_mut().for_each(|channel| *channel *= alpha);
+        });
+    }
+    linear
+}
+
+/// The opposite of `to_linear_premultiplied`, converting back to the same
+/// kind of image as `like`
+fn from_linear_premultiplied(mut linear: Rgba32FImage, premultiplied: bool, like: &DynamicImage) -> DynamicImage {
+    if !premultiplied {
+        linear.pixels_mut().for_each(|Rgba(pixel)| {
+            let alpha = pixel[3];
+            pixel[..3].iter_mut().for_each(|channel| {
+                *channel = if alpha > 0. { *channel / alpha } else { 0. };
+            });
+        });
+    }
+    match like {
+        DynamicImage::ImageRgba8(_) => DynamicImage::ImageRgba8(RgbaImage::from_fn(linear.width(), linear.height(), |x, y| {
+            let Rgba([r, g, b, a]) = *linear.get_pixel(x, y);
+            let channel = |value: f32| (linear_to_srgb(value.clamp(0., 1.)) * 255.).round() as u8;
+            Rgba([channel(r), channel(g), channel(b), (a.clamp(0., 1.) * 255.).round() as u8])
+        })),
+        DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba16(ImageBuffer::from_fn(linear.width(), linear.height(), |x, y| {
+            Rgba(linear.get_pixel(x, y).0.map(|channel| half::f16::from_f32(channel).to_bits()))
+        })),
+        _ => DynamicImage::ImageRgba32F(linear),
+    }
+}
+
+/// Halve the size of an image, averaging each 2x2 block of pixels. On odd
+/// sizes, the last row or column is left out, like the GPU does.
+fn downsample(image: &Rgba32FImage) -> Rgba32FImage {
+    let (width, height) = image.dimensions();
+    let (half_width, half_height) = mip_size(width, height, 1);
+    Rgba32FImage::from_fn(half_width, half_height, |x, y| {
+        let mut sum = [0.; 4];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let source = image.get_pixel((x * 2 + dx).min(width - 1), (y * 2 + dy).min(height - 1));
+            sum.iter_mut().zip(source.0).for_each(|(sum, channel)| *sum += channel);
+        }
+        Rgba(sum.map(|channel| channel / 4.))
+    })
+}
+
+/// Every mip level of an image that's been converted already, after the
+/// first one. Filtering happens in linear space with premultiplied alpha, so
+/// that mips don't get darker, and transparent pixels don't bleed their
+/// colour into their neighbours.
+fn mip_chain(image: &DynamicImage, level_count: u32, premultiplied: bool) -> Vec<DynamicImage> {
+    let mut level = to_linear_premultiplied(image, premultiplied);
+    (1..level_count).map(|_| {
+        level = downsample(&level);
+        from_linear_premultiplied(level.clone(), premultiplied, image)
+    }).collect()
+}
+
+/// Multiply the colour channels of an image by its alpha channel. The image
+/// has to be converted already, so it's either 8-bit sRGB, or 16-bit floats
+/// stored as `u16`s. sRGB images are premultiplied in linear space, since
+/// that's where they get blended.
+fn premultiply(image: DynamicImage) -> DynamicImage {
+    match image {
+        DynamicImage::ImageRgba8(mut buffer) => {
+            buffer.pixels_mut().for_each(|Rgba(pixel)| {
+                let alpha = pixel[3] as f32 / 255.;
+                pixel[..3].iter_mut().for_each(|channel| {
+                    let linear = srgb_to_linear(*channel as f32 / 255.) * alpha;
+                    *channel = (linear_to_srgb(linear) * 255.).round() as u8;
+                });
+            });
+            DynamicImage::ImageRgba8(buffer)
+        }
+        DynamicImage::ImageRgba16(mut buffer) => {
+            buffer.pixels_mut().for_each(|Rgba(pixel)| {
+                let alpha = half::f16::from_bits(pixel[3]).to_f32();
+                pixel[..3].iter_mut().for_each(|channel| {
+                    let value = half::f16::from_bits(*channel).to_f32() * alpha;
+                    *channel = half::f16::from_f32(value).to_bits();
+                });
+            });
+            DynamicImage::ImageRgba16(buffer)
+        }
+        image => image,
+    }
+}
+
+impl Texture {
+    /// Put several images into the layers of one texture, in order. If
+    /// `premultiply` is true, the colour channels are multiplied by alpha
+    /// before the texture is uploaded. The mips are drawn by `mip_generator`
+    /// if there is one, or resized on the CPU otherwise.
+    ///
+    /// KTX2 and DDS files are uploaded as they are, mips and all, if they
+    /// can be. Otherwise, they're decompressed in place, and treated like any
+    /// other image, so that another texture can be made from the same images
+    /// without decompressing them again.
+    pub fn from_loaded(
+        context: &GpuContext,
+        images: &mut [LoadedImage],
+        label: Option<&str>,
+        premultiply: bool,
+        mip_generator: Option<&MipGenerator>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let has_compressed = images.iter().any(|image| matches!(image, LoadedImage::Compressed(_)));
+        let uploadable = match compressed::uploadable(&context.device, images) {
+            // Block-compressed colours can't be premultiplied without
+            // compressing them again
+            Ok(_) if premultiply => Err(String::from("Block-compressed textures can't be premultiplied")),
+            uploadable => uploadable,
         };
-        let format = format?;
-        let image = match conversion {
-            Some(f) => f(image),
-            None => image,
+        match uploadable {
+            Ok(compressed) => return Texture::from_compressed(context, &compressed, label),
+            Err(reason) if has_compressed => platform::log(&format!("{reason}, so they'll be decompressed")),
+            Err(_) => (),
+        }
+        let images = images.iter_mut()
+            .map(|image| image.decode().cloned())
+            .collect::<Result<_, _>>()?;
+        Texture::from_images(context, images, label, premultiply, mip_generator)
+    }
+    /// Make a 2D array texture with one layer for each image. The images
+    /// don't have to be the same size, or the same format; they're resampled
+    /// to the size of the largest, and converted to a format that can hold
+    /// all of them.
+    pub fn from_images(
+        context: &GpuContext,
+        images: Vec<DynamicImage>,
+        label: Option<&str>,
+        premultiply: bool,
+        mip_generator: Option<&MipGenerator>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Texture::from_images_with_mip_limit(context, images, label, premultiply, mip_generator, u32::MAX)
+    }
+    /// Like `from_images`, but the mip chain stops after `max_mip_levels`
+    /// levels, rather than going all the way down to 1x1
+    pub(super) fn from_images_with_mip_limit(
+        context: &GpuContext,
+        images: Vec<DynamicImage>,
+        label: Option<&str>,
+        premultiply: bool,
+        mip_generator: Option<&MipGenerator>,
+        max_mip_levels: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (device, queue) = (&context.device, &context.queue);
+        if images.is_empty() {
+            return Err(Box::from("A texture needs at least one image"));
+        }
+        let width = images.iter().map(DynamicImage::width).max().unwrap_or(1);
+        let height = images.iter().map(DynamicImage::height).max().unwrap_or(1);
+        let mut format = TextureFormat::Rgba8UnormSrgb;
+        for image in &images {
+            if texture_format(image)? == TextureFormat::Rgba16Float {
+                format = TextureFormat::Rgba16Float;
+            }
+        }
+        let layers: Vec<DynamicImage> = images.into_iter().map(|image| {
+            let image = if (image.width(), image.height()) != (width, height) {
+                image.resize_exact(width, height, FilterType::CatmullRom)
+            } else {
+                image
+            };
+            let image = convert(image, format);
+            if premultiply { self::premultiply(image) } else { image }
+        }).collect();
+        let layer_count = layers.len() as u32;
+        let mip_level_count = mip_level_count(width, height).min(max_mip_levels.max(1));
+        let mip_generator = match mip_generator {
+            Some(_) if !MipGenerator::supports(device, format) => {
+                platform::log(&format!("Mips can't be drawn into {format:?} textures, so they'll be made on the CPU"));
+                None
+            }
+            mip_generator => mip_generator,
         };
-        let mip_level_count = MIP_LEVELS.max(1);
         let texture = device.create_texture(&TextureDescriptor {
             label,
             size: Extent3d {
                 width,
                 height,
-                depth_or_array_layers: 1,
+                depth_or_array_layers: array_layers(context, layer_count),
             },
             mip_level_count,
             sample_count: 1,
@@ -102,47 +331,109 @@ impl Texture {
             usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
             view_formats: &[format],
         });
-        for mip_level in 0..mip_level_count {
-            let nwidth = width >> mip_level;
-            let nheight = height >> mip_level;
-            let bytes_per_channel = match format {
-                TextureFormat::Rgba8UnormSrgb => 1,
-                TextureFormat::Rgba16Float => 2,
-                TextureFormat::Rgba32Float => 4,
-                _ => unreachable!()
-            };
-            let channels = match format {
-                TextureFormat::Rgba8UnormSrgb => 4,
-                TextureFormat::Rgba16Float => 4,
-                TextureFormat::Rgba32Float => 4,
-                _ => unreachable!()
-            };
-            let bytes_per_row = nwidth * channels * bytes_per_channel;
-            let data = if mip_level > 0 {
-                let filter = FilterType::CatmullRom;
-                let image = image.resize(nwidth, nheight, filter);
-                Cow::Owned(image.into_bytes())
-            } else {
-                Cow::from(image.as_bytes())
-            };
-            let data_layout = ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(bytes_per_row),
-                rows_per_image: None,
-            };
-            let copy = ImageCopyTexture {
-                texture: &texture,
-                mip_level,
-                origin: Origin3d::ZERO,
-                aspect: TextureAspect::All,
-            };
-            let size = Extent3d {
-                width: nwidth,
-                height: nheight,
-                depth_or_array_layers: 1,
+        let bytes_per_channel = match format {
+            TextureFormat::Rgba8UnormSrgb => 1,
+            TextureFormat::Rgba16Float => 2,
+            TextureFormat::Rgba32Float => 4,
+            _ => unreachable!()
+        };
+        let channels = match format {
+            TextureFormat::Rgba8UnormSrgb => 4,
+            TextureFormat::Rgba16Float => 4,
+            TextureFormat::Rgba32Float => 4,
+            _ => unreachable!()
+        };
+        // Only the first level is uploaded if the GPU draws the rest
+        let uploaded_levels = match mip_generator {
+            Some(_) => 1,
+            None => mip_level_count,
+        };
+        for (layer, image) in layers.iter().enumerate() {
+            let mips = match uploaded_levels {
+                1 => Vec::new(),
+                levels => mip_chain(image, levels, premultiply),
             };
-            queue.write_texture(copy, data.borrow(), data_layout, size);
+            for mip_level in 0..uploaded_levels {
+                let (nwidth, nheight) = mip_size(width, height, mip_level);
+                let bytes_per_row = nwidth * channels * bytes_per_channel;
+                let data = match mip_level {
+                    0 => image.as_bytes(),
+                    level => mips[level as usize - 1].as_bytes(),
+                };
+                let data_layout = ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                };
+                let copy = ImageCopyTexture {
+                    texture: &texture,
+                    mip_level,
+                    origin: Origin3d { x: 0, y: 0, z: layer as u32 },
+                    aspect: TextureAspect::All,
+                };
+                let size = Extent3d {
+                    width: nwidth,
+                    height: nheight,
+                    depth_or_array_layers: 1,
+                };
+                queue.write_texture(copy, data, data_layout, size);
+            }
+        }
+        if let Some(mip_generator) = mip_generator {
+            mip_generator.generate(device, queue, &texture, premultiply);
+        }
+        Ok(Texture::new(device, texture, layer_count, label))
+    }
+    /// Make a 2D array texture with one layer for each block-compressed
+    /// image, with the mips that came with them. They have to be the same
+    /// format and size.
+    pub fn from_compressed(
+        context: &GpuContext,
+        images: &[&CompressedImage],
+        label: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (device, queue) = (&context.device, &context.queue);
+        let first = images.first().ok_or("A texture needs at least one image")?;
+        let (format, width, height) = (first.format, first.width, first.height);
+        let layer_count = images.len() as u32;
+        let mip_level_count = images.iter().map(|image| image.levels.len() as u32).min().unwrap_or(1);
+        let texture = device.create_texture(&TextureDescriptor {
+            label,
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: array_layers(context, layer_count),
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[format],
+        });
+        for (layer, image) in images.iter().enumerate() {
+            for (mip_level, data) in (0..mip_level_count).zip(&image.levels) {
+                let (bytes_per_row, rows) = image.level_layout(mip_level);
+                let data_layout = ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(rows),
+                };
+                let copy = ImageCopyTexture {
+                    texture: &texture,
+                    mip_level,
+                    origin: Origin3d { x: 0, y: 0, z: layer as u32 },
+                    aspect: TextureAspect::All,
+                };
+                queue.write_texture(copy, data, data_layout, image.level_extent(mip_level));
+            }
         }
+        Ok(Texture::new(device, texture, layer_count, label))
+    }
+    /// Make the sampler and view for a texture array with all its mip levels
+    fn new(device: &wgpu::Device, texture: wgpu::Texture, layer_count: u32, label: Option<&str>) -> Self {
+        let format = texture.format();
+        let mip_level_count = texture.mip_level_count();
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label,
             address_mode_u: wgpu::AddressMode::Repeat,
@@ -152,7 +443,7 @@ impl Texture {
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Linear,
             lod_min_clamp: 0.0,
-            lod_max_clamp: 4.0,
+            lod_max_clamp: mip_level_count as f32,
             compare: None,
             anisotropy_clamp: 1,
             border_color: None,
@@ -160,18 +451,162 @@ impl Texture {
         let view = texture.create_view(&wgpu::TextureViewDescriptor {
             label,
             format: Some(format),
-            dimension: Some(wgpu::TextureViewDimension::D2),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
             aspect: TextureAspect::All,
             base_mip_level: 0,
             mip_level_count: Some(mip_level_count),
             base_array_layer: 0,
-            array_layer_count: None,
+            array_layer_count: Some(layer_count),
         });
-        Ok(Self {
+        Self {
             texture,
             sampler,
             view,
-            format
-        })
+            layer_count,
+        }
+    }
+    pub fn format(&self) -> TextureFormat {
+        self.texture.format()
+    }
+    pub fn layer_count(&self) -> u32 {
+        self.layer_count
+    }
+}
+
+pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+/// Create a depth texture, and a view for it, to go along with a colour
+/// attachment of the given size and sample count.
+pub fn depth_texture(device: &wgpu::Device, width: u32, height: u32, sample_count: u32) -> (wgpu::Texture, wgpu::TextureView) {
+    let depth_texture = device.create_texture(&TextureDescriptor {
+        label: Some("My depth texture"),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[DEPTH_FORMAT],
+    });
+    let depth_texture_view = depth_texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("View for my depth texture"),
+        format: Some(DEPTH_FORMAT),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        aspect: TextureAspect::DepthOnly,
+        base_mip_level: 0,
+        mip_level_count: None,
+        base_array_layer: 0,
+        array_layer_count: None,
+    });
+    (depth_texture, depth_texture_view)
+}
+
+/// Create a multisampled colour texture to render into, which gets resolved
+/// into a single-sampled texture of the same format and size. Returns `None`
+/// if `sample_count` is 1, since there's nothing to resolve.
+pub fn msaa_texture_view(
+    device: &wgpu::Device,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> Option<wgpu::TextureView> {
+    if sample_count <= 1 {
+        return None;
+    }
+    let msaa_texture = device.create_texture(&TextureDescriptor {
+        label: Some("Multisampled colour texture"),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[format],
+    });
+    // The view keeps the texture alive
+    Some(SimpleTextureView::new(&msaa_texture, Some("Multisampled colour view")))
+}
+
+/// The attachments a frame gets rendered into
+pub struct RenderTarget<'a> {
+    pub color: &'a wgpu::TextureView,
+    /// Where to resolve `color` to, if it's multisampled
+    pub resolve: Option<&'a wgpu::TextureView>,
+    pub depth: &'a wgpu::TextureView,
+}
+
+impl<'a> RenderTarget<'a> {
+    /// Render into `output`, or into `msaa` and resolve it into `output` if
+    /// there is a multisampled texture.
+    pub fn new(output: &'a wgpu::TextureView, msaa: Option<&'a wgpu::TextureView>, depth: &'a wgpu::TextureView) -> Self {
+        match msaa {
+            Some(msaa) => Self { color: msaa, resolve: Some(output), depth },
+            None => Self { color: output, resolve: None, depth },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The average of each channel of an 8-bit image
+    fn average(image: &DynamicImage) -> [f32; 4] {
+        let image = image.to_rgba8();
+        let mut sum = [0.; 4];
+        image.pixels().for_each(|pixel| {
+            sum.iter_mut().zip(pixel.0).for_each(|(sum, channel)| *sum += channel as f32);
+        });
+        sum.map(|channel| channel / image.pixels().len() as f32)
+    }
+
+    #[test]
+    fn mip_chain_dimensions() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(256, 256), 9);
+        assert_eq!(mip_level_count(472, 816), 10);
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(10, 3));
+        let levels = mip_level_count(10, 3);
+        let sizes: Vec<(u32, u32)> = mip_chain(&image, levels, false).iter()
+            .map(|level| (level.width(), level.height()))
+            .collect();
+        assert_eq!(sizes, [(5, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn mip_chain_average_colour() {
+        // Black and white stripes average out to 50% grey in linear space,
+        // which is 188 in sRGB, not 128
+        let stripes = RgbaImage::from_fn(8, 8, |x, _| match x % 2 {
+            0 => Rgba([0, 0, 0, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+        let stripes = DynamicImage::ImageRgba8(stripes);
+        for level in mip_chain(&stripes, mip_level_count(8, 8), false) {
+            assert_eq!(average(&level), [188., 188., 188., 255.]);
+        }
+        // Transparent pixels don't add their colour to the opaque ones
+        let half_transparent = RgbaImage::from_fn(8, 8, |x, _| match x % 2 {
+            0 => Rgba([255, 0, 0, 255]),
+            _ => Rgba([0, 255, 0, 0]),
+        });
+        let half_transparent = DynamicImage::ImageRgba8(half_transparent);
+        for level in mip_chain(&half_transparent, mip_level_count(8, 8), false) {
+            assert_eq!(average(&level), [255., 0., 0., 128.]);
+        }
+        // Premultiplied images are averaged as they are
+        let premultiplied = premultiply(half_transparent);
+        for level in mip_chain(&premultiplied, mip_level_count(8, 8), true) {
+            assert_eq!(average(&level), [188., 0., 0., 128.]);
+        }
     }
 }